@@ -1,8 +1,13 @@
+// libraries searched for symbols imported from `env`, in order
+#[link_section = "libwasm.needed"]
+#[used]
+static NEEDED:[u8; 14] = *b"libhello.wasm\n";
+
 fn main() {
     unsafe{hello_world()}
 }
 
-#[link(wasm_import_module = "libhello.wasm")]
+#[link(wasm_import_module = "env")]
 extern {
     fn hello_world();
-}
\ No newline at end of file
+}
@@ -0,0 +1,121 @@
+use wasmer::{Instance, Memory, Pages, Store, Type, Value};
+
+use crate::dylink;
+use crate::DEBUG;
+
+/// allocators a guest may export, tried in order. emscripten prefixes C names with an underscore
+/// and its sbrk owns the top of memory, so its allocator must be found before memory is grown
+const ALLOCATORS:&[&str] = &["__libwasm_alloc", "malloc", "_malloc"];
+
+const WASM_PAGE_SIZE:u64 = 65536;
+
+/// where the arguments were written in guest memory
+pub struct Argv{
+    pub argc:i32,
+    /// pointer to the argv array, typed for the entry point
+    pub argv:Value
+}
+
+/// copy the arguments into guest memory as C strings and a null terminated array of pointers.
+/// `pointer` is the type of argv in the entry point, i32 for wasm32 and i64 for memory64.
+/// `memory` is the memory the host gave the guest, if it does not export its own.
+pub fn write(store:&Store, instance:&Instance, memory:Option<&Memory>, args:&[&str], pointer:Type) -> Result<Argv, String>{
+    let width = match pointer{
+        Type::I32 => 4,
+        Type::I64 => 8,
+        _ => return Err(format!("argv must be a pointer, not {:?}", pointer))
+    };
+
+    let memory = match (instance.exports.get_memory("memory"), memory){
+        (Ok(m), _) => m.clone(),
+        (Err(_), Some(m)) => m.clone(),
+        // PIC executables import the shared memory
+        (Err(_), None) => dylink::context(store)?.memory.clone()
+    };
+
+    let strings:u64 = args.iter().map(|a|{a.len() as u64 + 1}).sum();
+    let array = (args.len() as u64 + 1) * width;
+    // strings first, the array after them aligned to the pointer width
+    let array_offset = (strings + width - 1) / width * width;
+    let size = array_offset + array;
+
+    let base = allocate(instance, &memory, size, pointer)?;
+
+    if DEBUG{
+        println!("writing {} argument(s), {} bytes at {:#x}.", args.len(), size, base);
+    }
+
+    if base.checked_add(size).map(|end|{end > memory.data_size()}).unwrap_or(true){
+        return Err(format!("argv at {:#x} does not fit in memory", base))
+    }
+
+    // the guest is not running, nothing else accesses its memory
+    let data = unsafe{ memory.data_unchecked_mut() };
+    let base_ = base as usize;
+
+    let mut offset = 0;
+    let mut pointers = Vec::with_capacity(args.len() + 1);
+    for arg in args{
+        pointers.push(base + offset as u64);
+
+        data[base_ + offset..base_ + offset + arg.len()].copy_from_slice(arg.as_bytes());
+        data[base_ + offset + arg.len()] = 0;
+        offset += arg.len() + 1;
+    }
+    pointers.push(0);
+
+    let array_start = base_ + array_offset as usize;
+    for (i, p) in pointers.iter().enumerate(){
+        let at = array_start + i * width as usize;
+        if width == 4{
+            data[at..at + 4].copy_from_slice(&(*p as u32).to_le_bytes());
+        } else{
+            data[at..at + 8].copy_from_slice(&p.to_le_bytes());
+        }
+    }
+
+    let argv = base + array_offset;
+    Ok(Argv {
+        argc: args.len() as i32,
+        argv: if width == 4{ Value::I32(argv as u32 as i32) } else{ Value::I64(argv as i64) }
+    })
+}
+
+/// space from the guest's allocator, or else fresh pages at the top of memory
+fn allocate(instance:&Instance, memory:&Memory, size:u64, pointer:Type) -> Result<u64, String>{
+    for name in ALLOCATORS{
+        let f = match instance.exports.get_function(name){
+            Ok(f) => f,
+            Err(_) => continue
+        };
+
+        let ty = f.ty();
+        let arg = match ty.params(){
+            [Type::I32] => Value::I32(size as i32),
+            [Type::I64] => Value::I64(size as i64),
+            _ => continue
+        };
+
+        let result = f.call(&[arg]).map_err(|e|{format!("{} trapped allocating argv: {}", name, e)})?;
+        let ptr = match result.first(){
+            Some(Value::I32(p)) => *p as u32 as u64,
+            Some(Value::I64(p)) => *p as u64,
+            _ => continue
+        };
+
+        if ptr == 0{
+            return Err(format!("{} could not allocate {} bytes for argv", name, size))
+        }
+        return Ok(ptr)
+    }
+
+    // pages the guest has never seen, its own memory.grow calls return pages after them
+    let pages = ((size + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE) as u32;
+    let old = memory.grow(Pages(pages)).map_err(|e|{format!("cannot reserve memory for argv: {}", e)})?;
+
+    let base = old.0 as u64 * WASM_PAGE_SIZE;
+    if pointer == Type::I32 && base + size > u32::MAX as u64{
+        return Err("no room for argv below 4 GiB".to_string())
+    }
+    Ok(base)
+}
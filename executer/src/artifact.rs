@@ -0,0 +1,187 @@
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use wasmer::{Module, Store};
+
+use crate::compiler::Backend;
+use crate::{cache, features, pin, signing};
+use crate::DEBUG;
+
+/// extension of precompiled modules, `libfoo.wasmu` next to `libfoo.wasm`
+pub const EXTENSION:&str = "wasmu";
+
+/// first line of an artifact, followed by the engine identity line, the compiler backend,
+/// the SHA-256 of the source module and the serialized module
+const MAGIC:&str = "LIBWASMU1";
+
+struct Header{
+    identity:String,
+    backend:String,
+    hash:String,
+    /// offset of the serialized module
+    len:usize
+}
+
+pub fn is_artifact(bytes:&[u8]) -> bool{
+    bytes.starts_with(MAGIC.as_bytes())
+}
+
+fn read_header(reader:&mut impl BufRead) -> Option<Header>{
+    let mut lines = [String::new(), String::new(), String::new(), String::new()];
+    let mut len = 0;
+
+    for line in lines.iter_mut(){
+        len += reader.read_line(line).ok()?;
+        if !line.ends_with('\n'){
+            return None
+        }
+        line.pop();
+    }
+
+    let [magic, identity, backend, hash] = lines;
+    if magic != MAGIC{
+        return None
+    }
+    Some(Header{identity, backend, hash, len})
+}
+
+/// the precompiled sibling of the module file of library `name`, `hash` is the SHA-256 of the module file.
+/// a stale artifact, or one from another engine or backend, is ignored.
+/// the header only says what its writer claims, it does not vouch for the native code:
+/// a pinned library never uses its sibling, and with --require-signed the artifact
+/// needs a detached signature of its own.
+pub fn sibling(path:&Path, name:&str, backend:Backend, hash:&str) -> Option<Vec<u8>>{
+    if path.extension().map(|e|{e != "wasm"}).unwrap_or(true){
+        return None
+    }
+    let artifact = path.with_extension(EXTENSION);
+
+    if pin::is_pinned(name){
+        if DEBUG && artifact.is_file(){
+            println!("ignoring {}, {} is pinned to the contents of {}.", artifact.display(), name, path.display());
+        }
+        return None
+    }
+
+    let bytes = std::fs::read(&artifact).ok()?;
+    let header = read_header(&mut &bytes[..])?;
+
+    if header.identity != cache::identity() || header.backend != backend.name() || header.hash != hash{
+        if DEBUG{
+            println!("ignoring {}, it was not compiled from {} by this engine and {}.", artifact.display(), path.display(), backend.name());
+        }
+        return None
+    }
+
+    if signing::is_required(){
+        if let Err(e) = signing::verify(&artifact, &bytes){
+            if DEBUG{
+                println!("ignoring {}: {}.", artifact.display(), e);
+            }
+            return None
+        }
+    }
+    Some(bytes)
+}
+
+/// load a precompiled module, it must have been compiled by `backend`
+pub fn load(store:&Store, backend:Backend, bytes:&[u8]) -> Result<Module, String>{
+    let header = read_header(&mut &bytes[..]).ok_or_else(||{"malformed artifact header".to_string()})?;
+
+    let identity = cache::identity();
+    if header.identity != identity{
+        return Err(format!("artifact was compiled for '{}' but this engine is '{}'", header.identity, identity))
+    }
+
+    if header.backend != backend.name(){
+        return Err(format!("artifact was compiled by {} but {} is in use, run with --compiler {}", header.backend, backend.name(), header.backend))
+    }
+
+    // the header matches the engine the payload was serialized by
+    unsafe{ Module::deserialize(store, &bytes[header.len..]) }.map_err(|e|{e.to_string()})
+}
+
+/// `libwasm [--compiler <backend>] compile <in.wasm> -o <out.wasmu>`
+pub fn command(store:&Store, backend:Backend, input:&Path, args:&[&str]){
+    let output = match args.iter().position(|a|{*a == "-o"}){
+        Some(i) => match args.get(i + 1){
+            Some(o) => PathBuf::from(o),
+            None => {
+                eprintln!("libwasm: compile: missing <out.wasmu> for -o");
+                std::process::exit(2);
+            }
+        },
+        None => input.with_extension(EXTENSION)
+    };
+
+    if let Err(e) = compile(store, backend, input, &output){
+        eprintln!("libwasm: compile: {}", e);
+        std::process::exit(1);
+    }
+    println!("wrote {}", output.display());
+}
+
+fn compile(store:&Store, backend:Backend, input:&Path, output:&Path) -> Result<(), String>{
+    let bytes = std::fs::read(input).map_err(|e|{format!("{}: {}", input.display(), e)})?;
+    if is_artifact(&bytes){
+        return Err(format!("{} is already precompiled", input.display()))
+    }
+
+    let module = Module::new(store, &bytes).map_err(|e|{
+        format!("cannot compile {}: {}", input.display(), features::explain(&e.to_string()))
+    })?;
+    let payload = module.serialize().map_err(|e|{e.to_string()})?;
+
+    let mut out = format!("{}\n{}\n{}\n{}\n", MAGIC, cache::identity(), backend.name(), pin::hash(&bytes)).into_bytes();
+    out.extend_from_slice(&payload);
+
+    std::fs::write(output, out).map_err(|e|{format!("{}: {}", output.display(), e)})
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn read_header_fields(){
+        let bytes = format!("{}\nengine\ncranelift\nabc123\npayload", MAGIC).into_bytes();
+        let header = read_header(&mut &bytes[..]).unwrap();
+
+        assert_eq!(header.identity, "engine");
+        assert_eq!(header.backend, "cranelift");
+        assert_eq!(header.hash, "abc123");
+        assert_eq!(&bytes[header.len..], b"payload");
+    }
+
+    #[test]
+    fn read_header_rejects_malformed(){
+        assert!(read_header(&mut &b"LIBWASMU0\na\nb\nc\n"[..]).is_none());
+        // the hash line is cut off
+        assert!(read_header(&mut &format!("{}\na\nb\nc", MAGIC).into_bytes()[..]).is_none());
+        assert!(read_header(&mut &b"\0asm\x01\0\0\0"[..]).is_none());
+    }
+
+    #[test]
+    fn sibling_with_forged_header_needs_a_signature(){
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("libwasm-artifact-{}.wasm", std::process::id()));
+        let artifact = path.with_extension(EXTENSION);
+
+        let source = b"\0asm\x01\0\0\0".to_vec();
+        let hash = pin::hash(&source);
+        let backend = Backend::preferred();
+
+        // anyone can write a header naming the public hash of the .wasm
+        let forged = format!("{}\n{}\n{}\n{}\nnot the compiled module", MAGIC, cache::identity(), backend.name(), hash);
+        std::fs::write(&path, &source).unwrap();
+        std::fs::write(&artifact, forged).unwrap();
+
+        assert!(sibling(&path, "libforged.wasm", backend, &hash).is_some());
+
+        signing::require_signed();
+        assert!(sibling(&path, "libforged.wasm", backend, &hash).is_none());
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&artifact);
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use wasmer::{Module, Store};
+
+use crate::compiler::Backend;
+use crate::pin;
+use crate::DEBUG;
+
+/// extension of serialized compiled modules in the cache directory
+const ENTRY_EXTENSION:&str = "wasmer";
+
+static DISABLED:AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static!{
+    /// everything besides the module contents and the compiler backend that changes the compiled code
+    static ref IDENTITY:RwLock<String> = RwLock::new(String::new());
+}
+
+pub fn disable(){
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// describe the engine modules are compiled with, part of every cache key
+pub fn set_identity(store:&Store, features:&wasmer::Features){
+    let target = store.engine().target();
+
+    *IDENTITY.write().unwrap() = format!(
+        "wasmer {} universal {} {:?} {:?}",
+        wasmer::VERSION, target.triple(), target.cpu_features(), features
+    );
+}
+
+/// the engine description set at startup, also written into precompiled artifacts.
+/// any backend's output loads into any store with this identity.
+pub fn identity() -> String{
+    IDENTITY.read().unwrap().clone()
+}
+
+/// $XDG_CACHE_HOME/libwasm, defaults to ~/.cache/libwasm
+pub fn directory() -> Option<PathBuf>{
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME"){
+        if !dir.is_empty(){
+            return Some(PathBuf::from(dir).join("libwasm"))
+        }
+    }
+    std::env::var_os("HOME").map(|home|{PathBuf::from(home).join(".cache").join("libwasm")})
+}
+
+fn entry_path(backend:Backend, hash:&str) -> Option<PathBuf>{
+    let identity = identity();
+    if identity.is_empty(){
+        return None
+    }
+
+    let key = pin::hash(format!("{}\n{}\n{}", hash, identity, backend.name()).as_bytes());
+    directory().map(|d|{d.join(format!("{}.{}", key, ENTRY_EXTENSION))})
+}
+
+/// a module with these contents previously compiled by `backend`
+pub fn load(store:&Store, backend:Backend, hash:&str) -> Option<Module>{
+    if DISABLED.load(Ordering::SeqCst){
+        return None
+    }
+
+    let path = entry_path(backend, hash)?;
+    if !path.is_file(){
+        return None
+    }
+
+    // entries are only written by `store` with a matching key
+    match unsafe{ Module::deserialize_from_file(store, &path) }{
+        Ok(module) => {
+            if DEBUG{
+                println!("loaded compiled module from {}", path.display());
+            }
+            Some(module)
+        },
+        Err(e) => {
+            // a truncated or corrupted entry is compiled again and replaced
+            eprintln!("libwasm: warning: ignoring cache entry {}: {}", path.display(), e);
+            let _ = std::fs::remove_file(&path);
+            None
+        }
+    }
+}
+
+/// save a compiled module, failures only cost a recompile next time
+pub fn store(module:&Module, backend:Backend, hash:&str){
+    if DISABLED.load(Ordering::SeqCst){
+        return;
+    }
+
+    let path = match entry_path(backend, hash){
+        Some(p) => p,
+        None => return
+    };
+
+    if let Err(e) = write_entry(module, &path){
+        if DEBUG{
+            println!("cannot write cache entry {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn write_entry(module:&Module, path:&Path) -> Result<(), String>{
+    if let Some(dir) = path.parent(){
+        std::fs::create_dir_all(dir).map_err(|e|{e.to_string()})?;
+    }
+
+    // concurrent runs never see a partial entry
+    let tmp = path.with_extension(format!("{}.{}", ENTRY_EXTENSION, std::process::id()));
+    module.serialize_to_file(&tmp).map_err(|e|{e.to_string()})?;
+    std::fs::rename(&tmp, path).map_err(|e|{
+        let _ = std::fs::remove_file(&tmp);
+        e.to_string()
+    })
+}
+
+fn entries(dir:&Path) -> Vec<(PathBuf, u64)>{
+    let entries = match std::fs::read_dir(dir){
+        Ok(e) => e,
+        Err(_) => return Vec::new()
+    };
+
+    entries.flatten()
+        .map(|e|{e.path()})
+        .filter(|p|{p.extension().map(|e|{e == ENTRY_EXTENSION}).unwrap_or(false)})
+        .map(|p|{
+            let size = std::fs::metadata(&p).map(|m|{m.len()}).unwrap_or(0);
+            (p, size)
+        })
+        .collect()
+}
+
+/// `libwasm cache clean|stats`
+pub fn command(args:&[String]){
+    let dir = match directory(){
+        Some(d) => d,
+        None => {
+            eprintln!("libwasm: cache: cannot find a cache directory, set XDG_CACHE_HOME");
+            std::process::exit(1);
+        }
+    };
+
+    match args.first().map(|a|{a.as_str()}){
+        Some("clean") => {
+            let mut removed = 0;
+            for (path, _) in entries(&dir){
+                match std::fs::remove_file(&path){
+                    Ok(_) => removed += 1,
+                    Err(e) => eprintln!("libwasm: cache: cannot remove {}: {}", path.display(), e)
+                }
+            }
+            println!("removed {} cached module(s) from {}", removed, dir.display());
+        },
+        Some("stats") => {
+            let entries = entries(&dir);
+            let size:u64 = entries.iter().map(|(_, s)|{s}).sum();
+
+            println!("directory: {}", dir.display());
+            println!("entries:   {}", entries.len());
+            println!("size:      {:.1} MiB", size as f64 / (1024.0 * 1024.0));
+        },
+        _ => {
+            eprintln!("usage: libwasm cache clean|stats");
+            std::process::exit(2);
+        }
+    }
+}
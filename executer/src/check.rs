@@ -0,0 +1,103 @@
+use crate::error::{LinkError, LinkErrorKind, EXIT_LINK_ERROR};
+use crate::graph::{Graph, Symbol};
+use crate::{format_ty, preload, scope, types_compatible};
+
+/// `libwasm check <executable.wasm>`
+/// verifies the whole import graph and reports every problem, exits non-zero if there is any.
+pub fn command(graph:&Graph){
+    let errors = check(graph);
+
+    for e in &errors{
+        eprintln!("libwasm: check: {}", e);
+    }
+
+    if !errors.is_empty(){
+        eprintln!("libwasm: check: {} problem(s) found", errors.len());
+        std::process::exit(EXIT_LINK_ERROR);
+    }
+
+    println!("{}: ok, {} module(s) linked", graph.nodes[0].name, graph.nodes.len());
+}
+
+pub fn check(graph:&Graph) -> Vec<LinkError>{
+    let mut errors = Vec::new();
+
+    for cycle in graph.cycles(){
+        errors.push(LinkError::new(LinkErrorKind::CyclicDependency{cycle}, Vec::new()));
+    }
+
+    for (index, node) in graph.nodes.iter().enumerate(){
+        let module = match &node.module{
+            Some(m) => m,
+            None => {
+                let kind = node.error.clone().unwrap_or_else(||{
+                    LinkErrorKind::LibraryNotFound{
+                        name: node.name.clone()
+                    }
+                });
+
+                // the loader stubs weak imports from a library it cannot find
+                if matches!(kind, LinkErrorKind::LibraryNotFound{..}) && only_weak(graph, &node.name){
+                    continue;
+                }
+                errors.push(LinkError::new(kind, graph.chain(index)));
+                continue;
+            }
+        };
+
+        // the importer is part of the chain of its own symbol errors
+        let mut chain = graph.chain(index);
+        chain.push(node.name.clone());
+
+        for import in module.imports(){
+            let (m, field) = (import.module(), import.name());
+
+            match graph.resolve(index, m, field){
+                Symbol::Found{ty, ..} => {
+                    if !types_compatible(import.ty(), &ty){
+                        errors.push(LinkError::new(LinkErrorKind::TypeMismatch{
+                            module: m.to_string(),
+                            field: field.to_string(),
+                            expected: format_ty(import.ty()),
+                            found: format_ty(&ty)
+                        }, chain.clone()));
+                    }
+                },
+                Symbol::Missing => {
+                    if !node.weak.contains(m, field){
+                        errors.push(LinkError::new(LinkErrorKind::ExportMissing{
+                            module: m.to_string(),
+                            field: field.to_string()
+                        }, chain.clone()));
+                    }
+                },
+                // reported once for the library node
+                Symbol::LibraryNotFound | Symbol::Host => {}
+            }
+        }
+    }
+    errors
+}
+
+/// whether every module that links against a library only imports weak symbols from it
+fn only_weak(graph:&Graph, name:&str) -> bool{
+    if preload::requested().iter().any(|p|{p == name}){
+        return false
+    }
+
+    graph.nodes.iter().all(|node|{
+        if !node.dependencies.iter().any(|d|{d == name}){
+            return true
+        }
+        let module = match &node.module{
+            Some(m) => m,
+            None => return true
+        };
+
+        // a needed library is loaded whether or not anything is imported from it
+        if scope::needed(module).iter().any(|n|{n == name}){
+            return false
+        }
+        module.imports().filter(|i|{i.module() == name}).all(|i|{node.weak.contains(name, i.name())})
+    })
+}
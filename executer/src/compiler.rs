@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use wasmer::{Features, Module, Store, UniversalEngine};
+
+use crate::ldconfig;
+use crate::DEBUG;
+
+/// a compiler backend, each one is behind the cargo feature of the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend{
+    Singlepass,
+    Cranelift,
+    Llvm
+}
+
+impl Backend{
+    pub fn parse(name:&str) -> Result<Self, String>{
+        let backend = match name{
+            "singlepass" => Backend::Singlepass,
+            "cranelift" => Backend::Cranelift,
+            "llvm" => Backend::Llvm,
+            _ => return Err(format!("unknown compiler '{}', expected singlepass, cranelift or llvm", name))
+        };
+
+        if !backend.is_available(){
+            return Err(format!("libwasm was built without the {0} compiler, rebuild with --features {0}", name))
+        }
+        Ok(backend)
+    }
+
+    pub fn name(&self) -> &'static str{
+        match self{
+            Backend::Singlepass => "singlepass",
+            Backend::Cranelift => "cranelift",
+            Backend::Llvm => "llvm"
+        }
+    }
+
+    pub fn is_available(&self) -> bool{
+        match self{
+            Backend::Singlepass => cfg!(feature = "singlepass"),
+            Backend::Cranelift => cfg!(feature = "cranelift"),
+            Backend::Llvm => cfg!(feature = "llvm")
+        }
+    }
+
+    /// cranelift when it is built in, otherwise whichever backend is
+    pub fn preferred() -> Self{
+        [Backend::Cranelift, Backend::Llvm, Backend::Singlepass].into_iter()
+            .find(|b|{b.is_available()})
+            .expect("libwasm needs at least one of the singlepass, cranelift or llvm features")
+    }
+
+    fn engine(&self, features:&Features) -> UniversalEngine{
+        match self{
+            #[cfg(feature = "singlepass")]
+            Backend::Singlepass => wasmer::Universal::new(wasmer::Singlepass::new()).features(features.clone()).engine(),
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => wasmer::Universal::new(wasmer::Cranelift::new()).features(features.clone()).engine(),
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => wasmer::Universal::new(wasmer::LLVM::new()).features(features.clone()).engine(),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("{} compiler is not built in", self.name())
+        }
+    }
+}
+
+struct Compilers{
+    main:Backend,
+    features:Features,
+    /// one store per backend in use
+    stores:HashMap<Backend, Store>
+}
+
+lazy_static::lazy_static!{
+    static ref COMPILERS:RwLock<Option<Compilers>> = RwLock::new(None);
+
+    /// backends chosen for single libraries in /etc/libwasm.conf
+    static ref OVERRIDES:HashMap<String, Backend> = {
+        let mut overrides = HashMap::new();
+        for (name, backend) in ldconfig::compiler_overrides(){
+            match Backend::parse(&backend){
+                Ok(b) => {
+                    overrides.insert(name, b);
+                },
+                Err(e) => eprintln!("libwasm: warning: {}: {}", ldconfig::CONFIG_FILE, e)
+            }
+        }
+        overrides
+    };
+}
+
+/// create the store the executable and its libraries are compiled in
+pub fn init(backend:Backend, features:&Features) -> Store{
+    let store = Store::new(&backend.engine(features));
+
+    let mut stores = HashMap::new();
+    stores.insert(backend, store.clone());
+
+    *COMPILERS.write().unwrap() = Some(Compilers {
+        main: backend,
+        features: features.clone(),
+        stores
+    });
+    store
+}
+
+pub fn main_backend() -> Backend{
+    COMPILERS.read().unwrap().as_ref().map(|c|{c.main}).unwrap_or_else(Backend::preferred)
+}
+
+/// the backend a library is compiled with, the main one unless /etc/libwasm.conf overrides it
+pub fn for_library(name:&str) -> Backend{
+    OVERRIDES.get(name).copied().unwrap_or_else(main_backend)
+}
+
+/// compile a module with `backend`, the module always belongs to the main store.
+/// code from another backend is compiled by an engine of its own and moved over serialized,
+/// so its instances share memories, tables and functions with everything else.
+pub fn compile(main:&Store, backend:Backend, bytes:&[u8]) -> Result<Module, String>{
+    if backend == main_backend(){
+        return Module::new(main, bytes).map_err(|e|{e.to_string()})
+    }
+
+    let store = {
+        let mut compilers = COMPILERS.write().unwrap();
+        let compilers = compilers.as_mut().ok_or_else(||{"compilers are not initialized".to_string()})?;
+
+        let features = compilers.features.clone();
+        compilers.stores.entry(backend).or_insert_with(||{
+            if DEBUG{
+                println!("creating {} engine.", backend.name());
+            }
+            Store::new(&backend.engine(&features))
+        }).clone()
+    };
+
+    let module = Module::new(&store, bytes).map_err(|e|{e.to_string()})?;
+    let serialized = module.serialize().map_err(|e|{e.to_string()})?;
+
+    // universal engines for the same target load each other's artifacts
+    unsafe{ Module::deserialize(main, &serialized) }.map_err(|e|{e.to_string()})
+}
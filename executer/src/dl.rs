@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use wasmer::{Array, Exportable, Extern, Function, LazyInit, Memory, Store, Value, WasmPtr, WasmerEnv};
+
+use crate::dylink;
+use crate::library::Library;
+use crate::DEBUG;
+
+/// host module exposing the dynamic linker to guests
+pub const DL_MODULE:&str = "libwasm:dl";
+
+lazy_static::lazy_static!{
+    static ref HANDLES:Mutex<Handles> = Mutex::new(Handles::default());
+
+    /// message of the last failure, cleared by dlerror
+    static ref LAST_ERROR:Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// libraries opened by guests, handle n is `libraries[n - 1]`.
+/// each entry counts its dlopen calls, the library is released by the matching last dlclose.
+#[derive(Default)]
+struct Handles{
+    libraries:Vec<Option<(Arc<Library>, u32)>>,
+    /// table slots handed out by dlsym, per handle and symbol
+    slots:HashMap<(i32, String), u32>
+}
+
+#[derive(WasmerEnv, Clone)]
+pub struct DlEnv{
+    /// missing when the guest imports its memory instead of exporting it
+    #[wasmer(export(optional = true))]
+    memory:LazyInit<Memory>,
+    store:Store
+}
+
+impl DlEnv{
+    /// the caller's memory, PIC modules that import memory use the shared one
+    fn memory(&self) -> Option<Memory>{
+        match self.memory_ref(){
+            Some(m) => Some(m.clone()),
+            None => dylink::context(&self.store).ok().map(|ctx|{ctx.memory.clone()})
+        }
+    }
+
+    fn read_string(&self, ptr:i32, len:i32) -> Option<String>{
+        WasmPtr::<u8, Array>::new(ptr as u32).get_utf8_string(&self.memory()?, len as u32)
+    }
+}
+
+fn set_error(message:String){
+    if DEBUG{
+        println!("dl: {}", message);
+    }
+    *LAST_ERROR.lock().unwrap() = Some(message);
+}
+
+pub fn resolve(store:&Store, field:&str) -> Option<wasmer::Export>{
+    let env = DlEnv{
+        memory: LazyInit::new(),
+        store: store.clone()
+    };
+
+    let f = match field{
+        "dlopen" => Function::new_native_with_env(store, env, dlopen),
+        "dlsym" => Function::new_native_with_env(store, env, dlsym),
+        "dlclose" => Function::new_native_with_env(store, env, dlclose),
+        "dlerror" => Function::new_native_with_env(store, env, dlerror),
+        _ => return None
+    };
+    Some(Extern::Function(f).to_export())
+}
+
+/// `dlopen(path, path_len) -> handle`, 0 on failure.
+/// the library is found and loaded like any import.
+fn dlopen(env:&DlEnv, path:i32, path_len:i32) -> i32{
+    let name = match env.read_string(path, path_len){
+        Some(n) => n,
+        None => {
+            set_error("dlopen: invalid path".to_string());
+            return 0
+        }
+    };
+
+    let library = match crate::resolve_import(&env.store, &name){
+        Ok(l) => l,
+        Err(e) => {
+            set_error(format!("dlopen: {}", e));
+            return 0
+        }
+    };
+
+    let mut handles = HANDLES.lock().unwrap();
+
+    // opening a library twice gives the same handle
+    for (i, entry) in handles.libraries.iter_mut().enumerate(){
+        if let Some((l, count)) = entry{
+            if Arc::ptr_eq(l, &library){
+                *count += 1;
+                return i as i32 + 1
+            }
+        }
+    }
+
+    handles.libraries.push(Some((library, 1)));
+    handles.libraries.len() as i32
+}
+
+/// position of a handle in the library list, none for handles dlopen cannot have returned
+fn index(handle:i32) -> Option<usize>{
+    usize::try_from(handle).ok().and_then(|h|{h.checked_sub(1)})
+}
+
+/// `dlsym(handle, name, name_len) -> address`.
+/// functions return their index in the shared function table, globals the address of the data.
+/// 0 on failure.
+fn dlsym(env:&DlEnv, handle:i32, name:i32, name_len:i32) -> i32{
+    let symbol = match env.read_string(name, name_len){
+        Some(s) => s,
+        None => {
+            set_error("dlsym: invalid symbol name".to_string());
+            return 0
+        }
+    };
+
+    let mut handles = HANDLES.lock().unwrap();
+
+    let library = match index(handle).and_then(|i|{handles.libraries.get(i)}).cloned().flatten(){
+        Some((l, _)) => l,
+        None => {
+            set_error(format!("dlsym: invalid handle {}", handle));
+            return 0
+        }
+    };
+
+    let key = (handle, symbol.clone());
+    if let Some(slot) = handles.slots.get(&key){
+        return *slot as i32
+    }
+
+    let lib = &library.name;
+    match library.instance.exports.get_extern(&symbol){
+        Some(Extern::Function(f)) => {
+            match dylink::context(&env.store).and_then(|ctx|{ctx.add_function(f)}){
+                Ok(slot) => {
+                    handles.slots.insert(key, slot);
+                    slot as i32
+                },
+                Err(e) => {
+                    set_error(format!("dlsym: {}", e));
+                    0
+                }
+            }
+        },
+        Some(Extern::Global(g)) => {
+            match g.get(){
+                Value::I32(v) => dylink::memory_base(lib).wrapping_add(v as u32) as i32,
+                _ => {
+                    set_error(format!("dlsym: {} in {} is not an address", symbol, lib));
+                    0
+                }
+            }
+        },
+        _ => {
+            set_error(format!("dlsym: undefined symbol {} in {}", symbol, lib));
+            0
+        }
+    }
+}
+
+/// `dlclose(handle) -> 0`, -1 for an invalid handle.
+/// the last close of a handle releases its table slots and its reference to the library,
+/// which is unloaded once nothing else links against it.
+fn dlclose(env:&DlEnv, handle:i32) -> i32{
+    let mut handles = HANDLES.lock().unwrap();
+
+    let closed = match index(handle).and_then(|i|{handles.libraries.get_mut(i)}){
+        Some(entry @ Some(_)) => {
+            let count = &mut entry.as_mut().unwrap().1;
+            *count -= 1;
+            if *count > 0{
+                return 0
+            }
+            entry.take()
+        },
+        _ => None
+    };
+
+    let (library, _) = match closed{
+        Some(c) => c,
+        None => {
+            drop(handles);
+            set_error(format!("dlclose: invalid handle {}", handle));
+            return -1
+        }
+    };
+
+    let mut slots = Vec::new();
+    handles.slots.retain(|(h, _), slot|{
+        if *h == handle{
+            slots.push(*slot);
+            return false
+        }
+        true
+    });
+    drop(handles);
+
+    // slots were handed out from the context, so it exists
+    if let Ok(ctx) = dylink::context(&env.store){
+        for slot in slots{
+            ctx.remove_function(slot);
+        }
+    }
+
+    // the library may run its destructors here, the handle table is not locked
+    drop(library);
+    0
+}
+
+/// `dlerror(buf, buf_len) -> len`, copies the last error into buf and clears it.
+/// returns 0 when there is no error, the message is truncated to fit.
+fn dlerror(env:&DlEnv, buf:i32, buf_len:i32) -> i32{
+    let message = match LAST_ERROR.lock().unwrap().take(){
+        Some(m) => m,
+        None => return 0
+    };
+
+    let bytes = message.as_bytes();
+    let len = bytes.len().min(buf_len.max(0) as usize);
+
+    let memory = match env.memory(){
+        Some(m) => m,
+        None => return 0
+    };
+    if let Some(cells) = WasmPtr::<u8, Array>::new(buf as u32).deref(&memory, 0, len as u32){
+        for (cell, b) in cells.iter().zip(bytes){
+            cell.set(*b);
+        }
+    }
+    len as i32
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use wasmer::{Exportable, Extern, Function, Global, Instance, Memory, MemoryType, Module, Pages, Store, Table, TableType, Type, Value};
+
+use crate::DEBUG;
+
+/// custom section carrying the dynamic linking metadata of a PIC module.
+/// https://github.com/WebAssembly/tool-conventions/blob/main/DynamicLinking.md
+pub const SECTION_NAME:&str = "dylink.0";
+
+const WASM_DYLINK_MEM_INFO:u8 = 1;
+const WASM_DYLINK_NEEDED:u8 = 2;
+
+/// pages reserved for the shared stack
+const STACK_PAGES:u32 = 16;
+
+const WASM_PAGE_SIZE:u32 = 0x10000;
+
+#[derive(Debug, Default, Clone)]
+pub struct DylinkInfo{
+    pub memory_size:u32,
+    pub memory_align:u32,
+    pub table_size:u32,
+    pub table_align:u32,
+    /// libraries this module depends on, in load order
+    pub needed:Vec<String>
+}
+
+/// read the dylink.0 section, returns None for modules that are not position independent.
+pub fn parse(module:&Module) -> Option<DylinkInfo>{
+    let section = module.custom_sections(SECTION_NAME).next()?;
+    parse_section(&section)
+}
+
+fn parse_section(section:&[u8]) -> Option<DylinkInfo>{
+    let mut reader = Reader{data:section, pos:0};
+    let mut info = DylinkInfo::default();
+
+    while !reader.eof(){
+        let id = reader.byte()?;
+        let len = reader.leb()? as usize;
+        let end = reader.pos + len;
+
+        if id == WASM_DYLINK_MEM_INFO{
+            info.memory_size = reader.leb()?;
+            info.memory_align = reader.leb()?;
+            info.table_size = reader.leb()?;
+            info.table_align = reader.leb()?;
+
+        } else if id == WASM_DYLINK_NEEDED{
+            let count = reader.leb()?;
+            for _ in 0..count{
+                info.needed.push(reader.string()?);
+            }
+        }
+
+        // skip unknown subsections
+        reader.pos = end;
+    }
+
+    Some(info)
+}
+
+struct Reader<'a>{
+    data:&'a [u8],
+    pos:usize
+}
+
+impl<'a> Reader<'a>{
+    fn eof(&self) -> bool{
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Option<u8>{
+        let b = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn leb(&mut self) -> Option<u32>{
+        let mut result = 0u32;
+        let mut shift = 0;
+        loop{
+            let b = self.byte()?;
+            result |= ((b & 0x7f) as u32) << shift;
+            if b & 0x80 == 0{
+                return Some(result)
+            }
+            shift += 7;
+            if shift >= 35{
+                return None
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String>{
+        let len = self.leb()? as usize;
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// env imports provided by the dynamic linker
+pub const IMPORTS:&[&str] = &[
+    "memory",
+    "__indirect_function_table",
+    "__stack_pointer",
+    "__memory_base",
+    "__table_base"
+];
+
+lazy_static::lazy_static!{
+    static ref CONTEXT:RwLock<Option<Arc<DylinkContext>>> = RwLock::new(None);
+
+    /// memory and table types imported by the main module
+    static ref MAIN_TYPES:RwLock<(Option<MemoryType>, Option<TableType>)> = RwLock::new((None, None));
+
+    /// memory base of every loaded module, 0 for modules that are not position independent
+    static ref MEMORY_BASES:RwLock<HashMap<String, u32>> = RwLock::new(HashMap::new());
+}
+
+pub fn set_memory_base(name:&str, base:u32){
+    MEMORY_BASES.write().unwrap().insert(name.to_string(), base);
+}
+
+/// data symbols exported by a module are relative to this address
+pub fn memory_base(name:&str) -> u32{
+    MEMORY_BASES.read().unwrap().get(name).copied().unwrap_or(0)
+}
+
+pub fn remove_memory_base(name:&str){
+    MEMORY_BASES.write().unwrap().remove(name);
+}
+
+/// memory, table and stack shared by the main module and every side module
+pub struct DylinkContext{
+    pub memory:Memory,
+    pub table:Table,
+    pub stack_pointer:Global,
+    lock:Mutex<FreeRegions>
+}
+
+/// regions released by unloaded modules as (start, length)
+#[derive(Default)]
+struct FreeRegions{
+    /// in pages
+    memory:Vec<(u32, u32)>,
+    /// in table slots
+    table:Vec<(u32, u32)>
+}
+
+/// first fit from a free list, the unused tail of the region stays free
+fn take_region(free:&mut Vec<(u32, u32)>, len:u32, align:u32) -> Option<u32>{
+    for i in 0..free.len(){
+        let (start, size) = free[i];
+        // computed in u64 so a large alignment cannot overflow
+        let (start, size, len) = (start as u64, size as u64, len as u64);
+        let base = align_up(start, align);
+
+        if base + len <= start + size{
+            free.remove(i);
+            if base > start{
+                free.push((start as u32, (base - start) as u32));
+            }
+            if base + len < start + size{
+                free.push(((base + len) as u32, (start + size - base - len) as u32));
+            }
+            return Some(base as u32)
+        }
+    }
+    None
+}
+
+fn align_up(value:u64, align:u32) -> u64{
+    let align = align as u64;
+    (value + align - 1) / align * align
+}
+
+/// the per-module placement of a side module
+#[derive(Debug, Clone, Copy)]
+pub struct Placement{
+    pub memory_base:u32,
+    pub memory_pages:u32,
+    pub table_base:u32,
+    pub table_size:u32
+}
+
+/// record the main module's memory and table imports, the shared context is created with them.
+/// must be called before the main module is instantiated.
+pub fn init(main:&Module){
+    let mut memory_ty = None;
+    let mut table_ty = None;
+
+    for import in main.imports(){
+        if import.module() != "env"{
+            continue;
+        }
+        match (import.name(), import.ty()){
+            ("memory", wasmer::ExternType::Memory(m)) => memory_ty = Some(m),
+            ("__indirect_function_table", wasmer::ExternType::Table(t)) => table_ty = Some(t),
+            _ => {}
+        }
+    }
+
+    *MAIN_TYPES.write().unwrap() = (memory_ty, table_ty);
+}
+
+/// the shared context, created on first use.
+/// fails when the main module's memory or table cannot hold the stack and the null slot.
+pub fn context(store:&Store) -> Result<Arc<DylinkContext>, String>{
+    if let Some(ctx) = CONTEXT.read().unwrap().as_ref(){
+        return Ok(ctx.clone())
+    }
+
+    let mut ctx = CONTEXT.write().unwrap();
+    if let Some(ctx) = ctx.as_ref(){
+        return Ok(ctx.clone())
+    }
+
+    let (memory_ty, table_ty) = *MAIN_TYPES.read().unwrap();
+
+    if DEBUG && memory_ty.is_none(){
+        println!("main module does not import env.memory, side modules will not share its memory.");
+    }
+    let created = Arc::new(DylinkContext::new(store, memory_ty, table_ty)?);
+    *ctx = Some(created.clone());
+    Ok(created)
+}
+
+impl DylinkContext{
+    fn new(store:&Store, memory_ty:Option<MemoryType>, table_ty:Option<TableType>) -> Result<Self, String>{
+        let memory_ty = memory_ty.unwrap_or(MemoryType::new(1, None, false));
+        let table_ty = table_ty.unwrap_or(TableType::new(Type::FuncRef, 0, None));
+
+        let memory = Memory::new(store, memory_ty).map_err(|e|{format!("cannot create shared memory: {}", e)})?;
+        let table = Table::new(store, table_ty, Value::FuncRef(None)).map_err(|e|{format!("cannot create shared table: {}", e)})?;
+
+        // index 0 stays null so a null function pointer never calls anything
+        if table.size() == 0{
+            table.grow(1, Value::FuncRef(None)).map_err(|e|{format!("cannot grow shared table: {}", e)})?;
+        }
+
+        // the stack grows downwards from the top of its reserved pages
+        let stack_base = memory.grow(STACK_PAGES).map_err(|e|{format!("cannot reserve stack: {}", e)})?;
+        let stack_top = (stack_base.0 + STACK_PAGES) as u64 * WASM_PAGE_SIZE as u64;
+        let stack_pointer = Global::new_mut(store, Value::I32(stack_top as u32 as i32));
+
+        Ok(Self {
+            memory,
+            table,
+            stack_pointer,
+            lock: Mutex::new(FreeRegions::default())
+        })
+    }
+
+    /// reserve memory for the data segments and table slots of a module.
+    /// regions released by unloaded modules are reused first.
+    pub fn allocate(&self, info:&DylinkInfo) -> Result<Placement, String>{
+        let mut free = self.lock.lock().unwrap();
+
+        // data is placed on whole pages, which covers any alignment up to 64KiB
+        let page_align = 1u64.checked_shl(info.memory_align)
+            .filter(|a|{*a <= 1 << 32})
+            .map(|a|{(a / WASM_PAGE_SIZE as u64).max(1) as u32})
+            .ok_or_else(||{format!("invalid memory alignment 2^{} in {}", info.memory_align, SECTION_NAME)})?;
+
+        let memory_pages = ((info.memory_size as u64 + WASM_PAGE_SIZE as u64 - 1) / WASM_PAGE_SIZE as u64) as u32;
+        let memory_base = if memory_pages == 0{
+            0
+        } else if let Some(page) = take_region(&mut free.memory, memory_pages, page_align){
+            page * WASM_PAGE_SIZE
+        } else{
+            let size = self.memory.size().0;
+            let base = align_up(size as u64, page_align);
+            let grow = base - size as u64 + memory_pages as u64;
+            if base + memory_pages as u64 > (u32::MAX as u64 + 1) / WASM_PAGE_SIZE as u64{
+                return Err(format!("{} pages of data do not fit in memory", memory_pages))
+            }
+            self.memory.grow(Pages(grow as u32))
+                .map_err(|e|{format!("cannot grow memory by {} pages for the module's data: {}", grow, e)})?;
+
+            // the padding in front of an aligned region can hold other modules
+            if base > size as u64{
+                free.memory.push((size, base as u32 - size));
+            }
+            base as u32 * WASM_PAGE_SIZE
+        };
+
+        let align = 1u32.checked_shl(info.table_align).ok_or_else(||{
+            format!("invalid table alignment 2^{} in {}", info.table_align, SECTION_NAME)
+        })?;
+
+        let table_base = if info.table_size == 0{
+            0
+        } else if let Some(base) = take_region(&mut free.table, info.table_size, align){
+            base
+        } else{
+            let size = self.table.size();
+            let table_base = align_up(size as u64, align);
+            let grow = table_base - size as u64 + info.table_size as u64;
+            if grow > u32::MAX as u64{
+                return Err(format!("{} table slots do not fit in the table", info.table_size))
+            }
+            self.table.grow(grow as u32, Value::FuncRef(None))
+                .map_err(|e|{format!("cannot grow table by {} slots for the module's functions: {}", grow, e)})?;
+            table_base as u32
+        };
+
+        Ok(Placement{
+            memory_base,
+            memory_pages,
+            table_base,
+            table_size: info.table_size
+        })
+    }
+
+    /// give back the regions of an unloaded module.
+    /// memory is zeroed since side modules expect fresh pages for their bss.
+    pub fn release(&self, placement:Placement){
+        let mut free = self.lock.lock().unwrap();
+
+        if placement.memory_pages > 0{
+            let start = placement.memory_base as usize;
+            let end = start + (placement.memory_pages * WASM_PAGE_SIZE) as usize;
+            unsafe{
+                self.memory.data_unchecked_mut()[start..end].fill(0);
+            }
+            free.memory.push((placement.memory_base / WASM_PAGE_SIZE, placement.memory_pages));
+        }
+
+        if placement.table_size > 0{
+            for i in placement.table_base..placement.table_base + placement.table_size{
+                let _ = self.table.set(i, Value::FuncRef(None));
+            }
+            free.table.push((placement.table_base, placement.table_size));
+        }
+    }
+
+    /// place a function in a free slot of the shared table, returns its index
+    pub fn add_function(&self, f:&Function) -> Result<u32, String>{
+        let mut free = self.lock.lock().unwrap();
+
+        if let Some(slot) = take_region(&mut free.table, 1, 1){
+            self.table.set(slot, Value::FuncRef(Some(f.clone()))).map_err(|e|{e.to_string()})?;
+            return Ok(slot)
+        }
+        self.table.grow(1, Value::FuncRef(Some(f.clone()))).map_err(|e|{format!("out of table slots: {}", e)})
+    }
+
+    /// clear a slot handed out by add_function
+    pub fn remove_function(&self, slot:u32){
+        let mut free = self.lock.lock().unwrap();
+
+        let _ = self.table.set(slot, Value::FuncRef(None));
+        free.table.push((slot, 1));
+    }
+
+    /// the env imports every PIC module expects
+    pub fn resolve(&self, store:&Store, placement:Option<Placement>, field:&str) -> Option<wasmer::Export>{
+        let ext = match field{
+            "memory" => Extern::Memory(self.memory.clone()),
+            "__indirect_function_table" => Extern::Table(self.table.clone()),
+            "__stack_pointer" => Extern::Global(self.stack_pointer.clone()),
+            "__memory_base" => Extern::Global(Global::new(store, Value::I32(placement?.memory_base as i32))),
+            "__table_base" => Extern::Global(Global::new(store, Value::I32(placement?.table_base as i32))),
+            _ => return None
+        };
+        Some(ext.to_export())
+    }
+}
+
+/// apply the data relocations of a PIC module, they read its offset table entries
+pub fn apply_relocations(instance:&Instance) -> Result<(), String>{
+    if let Ok(f) = instance.exports.get_function("__wasm_apply_data_relocs"){
+        f.call(&[]).map_err(|e|{format!("__wasm_apply_data_relocs trapped: {}", e)})?;
+    }
+    Ok(())
+}
+
+/// run the constructors of a side module, the main module runs its own from _start
+pub fn call_ctors(instance:&Instance) -> Result<(), String>{
+    if let Ok(f) = instance.exports.get_function("__wasm_call_ctors"){
+        f.call(&[]).map_err(|e|{format!("__wasm_call_ctors trapped: {}", e)})?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parse_mem_info_and_needed(){
+        let mut section = vec![WASM_DYLINK_MEM_INFO, 4, 16, 2, 3, 0];
+        // unknown subsections are skipped
+        section.extend_from_slice(&[9, 1, 0xff]);
+        section.extend_from_slice(&[WASM_DYLINK_NEEDED, 11, 2, 4]);
+        section.extend_from_slice(b"liba");
+        section.push(4);
+        section.extend_from_slice(b"libb");
+
+        let info = parse_section(&section).unwrap();
+        assert_eq!((info.memory_size, info.memory_align, info.table_size, info.table_align), (16, 2, 3, 0));
+        assert_eq!(info.needed, vec!["liba".to_string(), "libb".to_string()]);
+    }
+
+    #[test]
+    fn parse_truncated(){
+        assert!(parse_section(&[WASM_DYLINK_MEM_INFO, 4, 16]).is_none());
+    }
+
+    #[test]
+    fn take_region_splits_around_alignment(){
+        let mut free = vec![(1, 10)];
+        assert_eq!(take_region(&mut free, 4, 4), Some(4));
+
+        free.sort();
+        assert_eq!(free, vec![(1, 3), (8, 3)]);
+
+        assert_eq!(take_region(&mut free, 3, 1), Some(1));
+        assert_eq!(free, vec![(8, 3)]);
+        assert_eq!(take_region(&mut free, 4, 1), None);
+    }
+
+    #[test]
+    fn take_region_large_alignment(){
+        let mut free = vec![(1, u32::MAX - 1)];
+        assert_eq!(take_region(&mut free, 1, 1 << 31), Some(1 << 31));
+        assert_eq!(take_region(&mut vec![(1, 16)], 1, 1 << 31), None);
+    }
+}
@@ -0,0 +1,30 @@
+use wasmer::{Function, Type};
+
+/// entry point of a module without WASI or emscripten, unless --invoke names another export
+pub const DEFAULT_ENTRY:&str = "main";
+
+/// the entry point signatures the executer knows how to call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature{
+    /// `() -> ()`
+    Void,
+    /// `() -> i32`
+    Status,
+    /// `(i32 argc, ptr argv) -> i32`, ptr is i32 on wasm32 and i64 on memory64
+    Argv(Type)
+}
+
+pub fn detect(f:&Function) -> Result<Signature, String>{
+    let ty = f.ty();
+
+    match (ty.params(), ty.results()){
+        ([], []) => Ok(Signature::Void),
+        ([], [Type::I32]) => Ok(Signature::Status),
+        ([Type::I32, Type::I32], [Type::I32]) => Ok(Signature::Argv(Type::I32)),
+        ([Type::I32, Type::I64], [Type::I32]) => Ok(Signature::Argv(Type::I64)),
+        _ => Err(format!(
+            "unsupported entry point signature {:?} -> {:?}, expected () -> (), () -> i32, (i32, i32) -> i32 or (i32, i64) -> i32",
+            ty.params(), ty.results()
+        ))
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// exit code used when an executable cannot be linked, distinct from guest exit codes
+pub const EXIT_LINK_ERROR:i32 = 127;
+
+#[derive(Debug, Clone)]
+pub enum LinkErrorKind{
+    LibraryNotFound{
+        name:String
+    },
+    ExportMissing{
+        module:String,
+        field:String
+    },
+    TypeMismatch{
+        module:String,
+        field:String,
+        expected:String,
+        found:String
+    },
+    CyclicDependency{
+        cycle:Vec<String>
+    },
+    Compile{
+        path:String,
+        message:String
+    },
+    Instantiate{
+        module:String,
+        message:String
+    },
+    Untrusted{
+        path:String,
+        message:String
+    },
+    HashMismatch{
+        path:String,
+        expected:String,
+        found:String
+    }
+}
+
+/// a failure to load or link a module.
+/// `chain` lists the modules being loaded when it happened, the executable first.
+#[derive(Debug, Clone)]
+pub struct LinkError{
+    pub kind:LinkErrorKind,
+    pub chain:Vec<String>
+}
+
+impl LinkError{
+    pub fn new(kind:LinkErrorKind, chain:Vec<String>) -> Self{
+        Self {
+            kind,
+            chain
+        }
+    }
+}
+
+impl fmt::Display for LinkErrorKind{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            LinkErrorKind::LibraryNotFound { name } => {
+                write!(f, "library '{}' not found", name)
+            },
+            LinkErrorKind::ExportMissing { module, field } => {
+                write!(f, "undefined symbol {}::{}", module, field)
+            },
+            LinkErrorKind::TypeMismatch { module, field, expected, found } => {
+                write!(f, "{}::{} expected {} but library exports {}", module, field, expected, found)
+            },
+            LinkErrorKind::CyclicDependency { cycle } => {
+                write!(f, "cyclic dependency {}", cycle.join(" -> "))
+            },
+            LinkErrorKind::Compile { path, message } => {
+                write!(f, "cannot compile {}: {}", path, message)
+            },
+            LinkErrorKind::Instantiate { module, message } => {
+                write!(f, "cannot instantiate {}: {}", module, message)
+            },
+            LinkErrorKind::Untrusted { path, message } => {
+                write!(f, "refusing to load {}: {}", path, message)
+            },
+            LinkErrorKind::HashMismatch { path, expected, found } => {
+                write!(f, "{} has sha256 {} but {} is pinned", path, found, expected)
+            }
+        }
+    }
+}
+
+impl fmt::Display for LinkError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !self.chain.is_empty(){
+            write!(f, " (required by {})", self.chain.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LinkError{}
@@ -0,0 +1,60 @@
+use wasmer::{RuntimeError, TrapCode};
+use wasmer_wasi::WasiError;
+
+use crate::error::{LinkError, EXIT_LINK_ERROR};
+
+/// traps exit with 128 plus the signal a native program would have died from
+const SIGNAL_BASE:i32 = 128;
+
+const SIGILL:i32 = 4;
+const SIGABRT:i32 = 6;
+const SIGBUS:i32 = 7;
+const SIGFPE:i32 = 8;
+const SIGSEGV:i32 = 11;
+
+/// the signal matching a trap
+fn signal(code:TrapCode) -> i32{
+    match code{
+        TrapCode::StackOverflow
+        | TrapCode::HeapAccessOutOfBounds
+        | TrapCode::TableAccessOutOfBounds
+        | TrapCode::IndirectCallToNull => SIGSEGV,
+        TrapCode::HeapMisaligned | TrapCode::UnalignedAtomic => SIGBUS,
+        TrapCode::IntegerOverflow
+        | TrapCode::IntegerDivisionByZero
+        | TrapCode::BadConversionToInteger => SIGFPE,
+        TrapCode::UnreachableCodeReached | TrapCode::BadSignature => SIGILL,
+        #[allow(unreachable_patterns)]
+        _ => SIGABRT
+    }
+}
+
+/// the exit status for a guest that stopped with an error.
+/// WASI `proc_exit(n)` exits with n, a trap is reported and exits with 128 + signal,
+/// a failed lazy binding exits like any other link error,
+/// other errors raised by host functions count as an abort.
+pub fn status(error:RuntimeError) -> i32{
+    let error = match error.downcast::<WasiError>(){
+        Ok(WasiError::Exit(code)) => return code as i32,
+        Ok(e) => {
+            eprintln!("libwasm: {}", e);
+            return SIGNAL_BASE + SIGABRT
+        },
+        Err(e) => e
+    };
+
+    let error = match error.downcast::<LinkError>(){
+        Ok(e) => {
+            eprintln!("libwasm: link error: {}", e);
+            return EXIT_LINK_ERROR
+        },
+        Err(e) => e
+    };
+
+    eprintln!("libwasm: trap: {}", error);
+
+    match error.to_trap(){
+        Some(code) => SIGNAL_BASE + signal(code),
+        None => SIGNAL_BASE + SIGABRT
+    }
+}
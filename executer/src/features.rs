@@ -0,0 +1,107 @@
+use wasmer::Features;
+
+/// proposal names accepted by --enable-<name> and --disable-<name>
+pub const NAMES:&[&str] = &[
+    "threads", "reference-types", "simd", "bulk-memory", "multi-value", "tail-call",
+    "module-linking", "multi-memory", "memory64", "exceptions", "relaxed-simd", "extended-const"
+];
+
+/// wording of validation errors for each proposal, most specific first
+const ERROR_HINTS:&[(&str, &str)] = &[
+    ("relaxed simd", "relaxed-simd"),
+    ("simd", "simd"),
+    ("shared memor", "threads"),
+    ("threads", "threads"),
+    ("atomic", "threads"),
+    ("reference types", "reference-types"),
+    ("bulk memory", "bulk-memory"),
+    ("multi-value", "multi-value"),
+    ("multiple values", "multi-value"),
+    ("multi-memory", "multi-memory"),
+    ("multiple memories", "multi-memory"),
+    ("tail call", "tail-call"),
+    ("memory64", "memory64"),
+    ("64-bit memor", "memory64"),
+    ("exceptions", "exceptions"),
+    ("extended const", "extended-const"),
+    ("module linking", "module-linking")
+];
+
+/// `mvp` is WebAssembly 1.0, `stable` what Chrome, Firefox and Safari all ship by default,
+/// WebAssembly 2.0 plus tail calls, exceptions and extended constant expressions,
+/// `all` every proposal wasmer implements except module linking
+pub fn profile(name:&str) -> Result<Features, String>{
+    let mut features = mvp();
+
+    match name{
+        "mvp" => {},
+        "stable" => {
+            features.reference_types = true;
+            features.simd = true;
+            features.bulk_memory = true;
+            features.multi_value = true;
+            features.tail_call = true;
+            features.exceptions = true;
+            features.extended_const = true;
+        },
+        "all" => {
+            for name in NAMES{
+                if *name != "module-linking"{
+                    set(&mut features, name, true)?;
+                }
+            }
+        },
+        _ => return Err(format!("unknown features profile '{}', expected mvp, stable or all", name))
+    }
+    Ok(features)
+}
+
+fn mvp() -> Features{
+    Features {
+        threads: false,
+        reference_types: false,
+        simd: false,
+        bulk_memory: false,
+        multi_value: false,
+        tail_call: false,
+        module_linking: false,
+        multi_memory: false,
+        memory64: false,
+        exceptions: false,
+        relaxed_simd: false,
+        extended_const: false
+    }
+}
+
+pub fn set(features:&mut Features, name:&str, enabled:bool) -> Result<(), String>{
+    let flag = match name{
+        "threads" => &mut features.threads,
+        "reference-types" => &mut features.reference_types,
+        "simd" => &mut features.simd,
+        "bulk-memory" => &mut features.bulk_memory,
+        "multi-value" => &mut features.multi_value,
+        "tail-call" => &mut features.tail_call,
+        "module-linking" => &mut features.module_linking,
+        "multi-memory" => &mut features.multi_memory,
+        "memory64" => &mut features.memory64,
+        "exceptions" => &mut features.exceptions,
+        "relaxed-simd" => &mut features.relaxed_simd,
+        "extended-const" => &mut features.extended_const,
+        _ => return Err(format!("unknown feature '{}', expected one of {}", name, NAMES.join(", ")))
+    };
+    *flag = enabled;
+    Ok(())
+}
+
+/// name the disabled proposal a validation error is about, if it is one
+pub fn explain(message:&str) -> String{
+    let lower = message.to_lowercase();
+    if !lower.contains("enabled") && !lower.contains("feature") && !lower.contains("proposal"){
+        return message.to_string()
+    }
+
+    match ERROR_HINTS.iter().find(|(hint, _)|{lower.contains(hint)}){
+        Some((_, name)) => format!("module needs the {0} feature, which is disabled, run with --enable-{0} ({1})", name, message),
+        None => message.to_string()
+    }
+}
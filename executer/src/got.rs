@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use wasmer::{Exportable, Extern, Function, Global, Instance, Store, Value};
+
+use crate::dylink;
+use crate::DEBUG;
+
+/// pseudo module for data symbol addresses
+pub const GOT_MEM:&str = "GOT.mem";
+/// pseudo module for function table indices
+pub const GOT_FUNC:&str = "GOT.func";
+
+lazy_static::lazy_static!{
+    static ref GOT:Mutex<GlobalOffsetTable> = Mutex::new(GlobalOffsetTable::default());
+}
+
+/// the global offset table shared by every loaded module.
+/// each symbol gets one mutable global, filled once a module defining it is loaded.
+#[derive(Default)]
+struct GlobalOffsetTable{
+    mem:HashMap<String, Global>,
+    func:HashMap<String, Global>,
+    /// defining module and absolute address of data symbols, first definition wins
+    defined_mem:HashMap<String, (String, u32)>,
+    /// defining module and function of exported functions, first definition wins
+    defined_func:HashMap<String, (String, Function)>,
+    /// table slots already handed out to functions
+    func_slots:HashMap<String, u32>,
+    /// entries imported by some module that does not declare them weak
+    required:HashSet<(String, String)>
+}
+
+pub fn is_got_module(module:&str) -> bool{
+    module == GOT_MEM || module == GOT_FUNC
+}
+
+/// the entry for an import, `weak` when the importer declares it optional
+pub fn resolve(store:&Store, module:&str, field:&str, weak:bool) -> Option<wasmer::Export>{
+    let mut got = GOT.lock().unwrap();
+
+    if is_got_module(module) && !weak{
+        got.required.insert((module.to_string(), field.to_string()));
+    }
+
+    let global = if module == GOT_MEM{
+        if let Some(g) = got.mem.get(field){
+            g.clone()
+        } else{
+            let g = Global::new_mut(store, Value::I32(0));
+            if let Some((_, addr)) = got.defined_mem.get(field){
+                g.set(Value::I32(*addr as i32)).unwrap();
+            }
+            got.mem.insert(field.to_string(), g.clone());
+            g
+        }
+
+    } else if module == GOT_FUNC{
+        if let Some(g) = got.func.get(field){
+            g.clone()
+        } else{
+            let g = Global::new_mut(store, Value::I32(0));
+            if let Some((_, f)) = got.defined_func.get(field).cloned(){
+                if let Some(slot) = got.slot(store, field, &f){
+                    g.set(Value::I32(slot as i32)).unwrap();
+                }
+            }
+            got.func.insert(field.to_string(), g.clone());
+            g
+        }
+
+    } else{
+        return None
+    };
+
+    if DEBUG{
+        println!("{}.{} bound to global offset table.", module, field);
+    }
+    Some(Extern::Global(global).to_export())
+}
+
+/// record the exports of a loaded module and fill the entries waiting for them.
+/// data symbols of PIC modules are relative to their memory base.
+pub fn register(store:&Store, module:&str, instance:&Instance, memory_base:u32){
+    let mut got = GOT.lock().unwrap();
+
+    for (name, ext) in instance.exports.iter(){
+        match ext{
+            Extern::Global(g) => {
+                if got.defined_mem.contains_key(name){
+                    continue;
+                }
+                let addr = match g.get(){
+                    Value::I32(v) => memory_base.wrapping_add(v as u32),
+                    _ => continue
+                };
+                got.defined_mem.insert(name.clone(), (module.to_string(), addr));
+
+                if let Some(entry) = got.mem.get(name){
+                    entry.set(Value::I32(addr as i32)).unwrap();
+                }
+            },
+            Extern::Function(f) => {
+                if got.defined_func.contains_key(name){
+                    continue;
+                }
+                got.defined_func.insert(name.clone(), (module.to_string(), f.clone()));
+
+                if let Some(entry) = got.func.get(name).cloned(){
+                    if let Some(slot) = got.slot(store, name, f){
+                        entry.set(Value::I32(slot as i32)).unwrap();
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// drop the definitions of an unloaded module.
+/// its functions are cleared from the shared table so stale pointers trap instead of running freed code.
+pub fn unregister(store:&Store, module:&str){
+    let mut got = GOT.lock().unwrap();
+
+    got.defined_mem.retain(|_, (m, _)|{m != module});
+
+    let removed:Vec<String> = got.defined_func.iter()
+        .filter(|(_, (m, _))|{m == module})
+        .map(|(name, _)|{name.clone()})
+        .collect();
+
+    for name in removed{
+        got.defined_func.remove(&name);
+        if let Some(slot) = got.func_slots.remove(&name){
+            if let Ok(ctx) = dylink::context(store){
+                ctx.remove_function(slot);
+            }
+        }
+    }
+}
+
+/// required entries no loaded module defines, they would read as a null address
+pub fn undefined() -> Vec<(String, String)>{
+    let got = GOT.lock().unwrap();
+
+    let mut undefined:Vec<(String, String)> = got.required.iter()
+        .filter(|(module, field)|{
+            if module == GOT_MEM{
+                !got.defined_mem.contains_key(field)
+            } else{
+                !got.defined_func.contains_key(field)
+            }
+        })
+        .cloned()
+        .collect();
+    undefined.sort();
+    undefined
+}
+
+impl GlobalOffsetTable{
+    /// place a function in the shared indirect function table, once per symbol.
+    /// a full table leaves the entry null, calls through it trap.
+    fn slot(&mut self, store:&Store, name:&str, f:&Function) -> Option<u32>{
+        if let Some(slot) = self.func_slots.get(name){
+            return Some(*slot)
+        }
+
+        let slot = match dylink::context(store).and_then(|ctx|{ctx.add_function(f)}){
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("libwasm: warning: cannot place {} in the function table: {}", name, e);
+                return None
+            }
+        };
+
+        self.func_slots.insert(name.to_string(), slot);
+        Some(slot)
+    }
+}
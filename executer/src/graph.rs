@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use wasmer::{ExternType, Module, Store};
+
+use crate::error::LinkErrorKind;
+use crate::weak::WeakImports;
+use crate::{dl, dylink, got, pin, preload, scope, search};
+
+/// import modules provided by wasmer-wasi
+const WASI_MODULES:&[&str] = &["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// a module reached while walking the imports of an executable
+pub struct Node{
+    pub name:String,
+    pub path:Option<PathBuf>,
+    pub module:Option<Module>,
+    /// why the module could not be loaded
+    pub error:Option<LinkErrorKind>,
+    /// libraries it links against, in declaration order
+    pub dependencies:Vec<String>,
+    pub weak:WeakImports,
+    /// the node that first required this one
+    pub parent:Option<usize>,
+    is_wasi:bool,
+    is_emscripten:bool
+}
+
+/// where an import would be resolved from
+pub enum Symbol{
+    /// provided by the host environment or the linker itself
+    Host,
+    Found{
+        library:usize,
+        ty:ExternType
+    },
+    LibraryNotFound,
+    Missing
+}
+
+/// the transitive dependencies of an executable, compiled but not instantiated.
+/// nodes are in breadth first order with the executable first, the order of the global scope.
+pub struct Graph{
+    pub nodes:Vec<Node>,
+    index:HashMap<String, usize>
+}
+
+impl Node{
+    fn new(name:&str, path:Option<PathBuf>, module:Option<Module>, error:Option<LinkErrorKind>) -> Self{
+        let mut node = Self {
+            name: name.to_string(),
+            path,
+            module: None,
+            error,
+            dependencies: Vec::new(),
+            weak: WeakImports::default(),
+            parent: None,
+            is_wasi: false,
+            is_emscripten: false
+        };
+
+        let m = match module{
+            Some(m) => m,
+            None => return node
+        };
+
+        node.is_wasi = wasmer_wasi::is_wasi_module(&m);
+        node.is_emscripten = wasmer_emscripten::is_emscripten_module(&m);
+        node.weak = WeakImports::parse(&m);
+        pin::record(&m);
+
+        let mut deps = scope::needed(&m);
+        for import in m.imports(){
+            let module = import.module();
+            if !node.is_host_module(module) && module != scope::SCOPE_MODULE && !deps.iter().any(|d|{d == module}){
+                deps.push(module.to_string());
+            }
+        }
+        node.dependencies = deps;
+        node.module = Some(m);
+        node
+    }
+
+    /// import modules that are not library files
+    fn is_host_module(&self, module:&str) -> bool{
+        if module == dl::DL_MODULE{
+            return true
+        }
+        if self.is_wasi && WASI_MODULES.contains(&module){
+            return true
+        }
+        if self.is_emscripten && (module == "env" || module == "asm2wasm" || module.starts_with("global")){
+            return true
+        }
+        false
+    }
+}
+
+impl Graph{
+    pub fn build(store:&Store, module:&Module, name:&str, path:&Path) -> Self{
+        let mut root = Node::new(name, Some(path.to_path_buf()), Some(module.clone()), None);
+
+        // preload libraries come first in the global scope
+        let mut deps = preload::requested();
+        for d in root.dependencies.drain(..){
+            if !deps.contains(&d){
+                deps.push(d);
+            }
+        }
+        root.dependencies = deps;
+
+        let mut graph = Self {
+            nodes: Vec::new(),
+            index: HashMap::new()
+        };
+        graph.index.insert(name.to_string(), 0);
+        graph.nodes.push(root);
+
+        let mut queue = VecDeque::from(vec![0]);
+        while let Some(i) = queue.pop_front(){
+            for dep in graph.nodes[i].dependencies.clone(){
+                if graph.index.contains_key(&dep){
+                    continue;
+                }
+
+                let mut node = load_node(store, &dep);
+                node.parent = Some(i);
+                graph.index.insert(dep, graph.nodes.len());
+                queue.push_back(graph.nodes.len());
+                graph.nodes.push(node);
+            }
+        }
+        graph
+    }
+
+    pub fn find(&self, name:&str) -> Option<usize>{
+        self.index.get(name).copied()
+    }
+
+    /// the modules that led to a node, the executable first
+    pub fn chain(&self, index:usize) -> Vec<String>{
+        let mut chain = Vec::new();
+        let mut current = self.nodes[index].parent;
+        while let Some(i) = current{
+            chain.push(self.nodes[i].name.clone());
+            current = self.nodes[i].parent;
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// dependency cycles, each as the list of modules from the first back to itself
+    pub fn cycles(&self) -> Vec<Vec<String>>{
+        let mut cycles = Vec::new();
+        let mut state = vec![0u8; self.nodes.len()];
+        let mut stack = Vec::new();
+        self.find_cycles(0, &mut state, &mut stack, &mut cycles);
+        cycles
+    }
+
+    /// depth first search, state is 0 unvisited, 1 on the stack, 2 done
+    fn find_cycles(&self, i:usize, state:&mut Vec<u8>, stack:&mut Vec<usize>, cycles:&mut Vec<Vec<String>>){
+        state[i] = 1;
+        stack.push(i);
+
+        for dep in &self.nodes[i].dependencies{
+            let j = match self.find(dep){
+                Some(j) => j,
+                None => continue
+            };
+
+            if state[j] == 1{
+                let pos = stack.iter().position(|k|{*k == j}).unwrap();
+                let mut cycle:Vec<String> = stack[pos..].iter().map(|k|{self.nodes[*k].name.clone()}).collect();
+                cycle.push(self.nodes[j].name.clone());
+                cycles.push(cycle);
+
+            } else if state[j] == 0{
+                self.find_cycles(j, state, stack, cycles);
+            }
+        }
+
+        stack.pop();
+        state[i] = 2;
+    }
+
+    /// where an import of a node would come from at run time
+    pub fn resolve(&self, importer:usize, module:&str, field:&str) -> Symbol{
+        let node = &self.nodes[importer];
+
+        // the linker provides the entry, it is null unless some module defines the symbol
+        if got::is_got_module(module){
+            let defined = self.nodes.iter().filter_map(|n|{n.module.as_ref()}).any(|m|{
+                match export_type(m, field){
+                    Some(ExternType::Global(_)) => module == got::GOT_MEM,
+                    Some(ExternType::Function(_)) => module == got::GOT_FUNC,
+                    _ => false
+                }
+            });
+            return if defined{ Symbol::Host } else{ Symbol::Missing }
+        }
+
+        if node.is_host_module(module){
+            return Symbol::Host
+        }
+
+        if module == scope::SCOPE_MODULE{
+            if dylink::IMPORTS.contains(&field){
+                return Symbol::Host
+            }
+
+            for (i, n) in self.nodes.iter().enumerate(){
+                if i == importer{
+                    continue;
+                }
+                if let Some(ty) = n.module.as_ref().and_then(|m|{export_type(m, field)}){
+                    return Symbol::Found{library: i, ty}
+                }
+            }
+            return Symbol::Missing
+        }
+
+        let i = match self.find(module){
+            Some(i) => i,
+            None => return Symbol::LibraryNotFound
+        };
+
+        match &self.nodes[i].module{
+            Some(m) => match export_type(m, field){
+                Some(ty) => Symbol::Found{library: i, ty},
+                None => Symbol::Missing
+            },
+            None => Symbol::LibraryNotFound
+        }
+    }
+}
+
+/// read and compile a library the way the loader would, without instantiating it
+fn load_node(store:&Store, name:&str) -> Node{
+    let path = match search::find_library(name){
+        Some(p) => p,
+        None => return Node::new(name, None, None, Some(LinkErrorKind::LibraryNotFound{name: name.to_string()}))
+    };
+
+    let result = crate::read_library(name, &path).and_then(|(bytes, hash)|{
+        crate::compile_library(store, name, &path, &bytes, &hash)
+    });
+
+    match result{
+        Ok(m) => Node::new(name, Some(path), Some(m), None),
+        Err(e) => Node::new(name, Some(path), None, Some(e.kind))
+    }
+}
+
+fn export_type(module:&Module, field:&str) -> Option<ExternType>{
+    module.exports().find(|e|{e.name() == field}).map(|e|{e.ty().clone()})
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use wasmer::{Function, FunctionType, RuntimeError, Store};
+
+use crate::error::LinkErrorKind;
+use crate::library::Library;
+use crate::{weak, DEBUG};
+
+/// when set, function imports are bound eagerly even in lazy mode, like LD_BIND_NOW
+pub const BIND_NOW_VAR:&str = "LIBWASM_BIND_NOW";
+
+static LAZY:AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static!{
+    /// imports bound to trampolines because the executable defining them was not instantiated yet,
+    /// they are checked once it is
+    static ref DEFERRED:Mutex<Vec<(String, String, FunctionType)>> = Mutex::new(Vec::new());
+}
+
+pub fn enable(){
+    LAZY.store(true, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool{
+    if std::env::var_os(BIND_NOW_VAR).map(|v|{!v.is_empty()}).unwrap_or(false){
+        return false
+    }
+    LAZY.load(Ordering::SeqCst)
+}
+
+/// a trampoline for an import the executable defines, recorded so it can be checked eagerly
+pub fn defer(store:&Store, ty:&FunctionType, module:&str, field:&str) -> Function{
+    DEFERRED.lock().unwrap().push((module.to_string(), field.to_string(), ty.clone()));
+    trampoline(store, ty, module, field, false)
+}
+
+pub fn take_deferred() -> Vec<(String, String, FunctionType)>{
+    std::mem::take(&mut *DEFERRED.lock().unwrap())
+}
+
+/// a host function of the import's signature that binds the real function on its first call.
+/// later calls go straight to the bound function.
+/// a `weak` import that cannot be found is bound to the same trapping stub as in eager mode.
+pub fn trampoline(store:&Store, ty:&FunctionType, module:&str, field:&str, weak:bool) -> Function{
+    let target:Arc<Mutex<Option<(Option<Arc<Library>>, Function)>>> = Arc::new(Mutex::new(None));
+
+    let store_ = store.clone();
+    let expected = ty.clone();
+    let module = module.to_string();
+    let field = field.to_string();
+
+    Function::new(store, ty, move |args|{
+        let bound = target.lock().unwrap().clone();
+
+        let f = match bound{
+            Some((_, f)) => f,
+            None => {
+                if DEBUG{
+                    println!("lazy binding {}.{} on first call.", module, field);
+                }
+
+                // the lock is not held while binding, loading a library may run its constructors.
+                // the link error unwinds out of the guest and is reported by exit::status
+                let (library, f) = match crate::bind_function(&store_, &module, &field, &expected){
+                    Ok((library, f)) => (Some(library), f),
+                    Err(e) => match e.kind{
+                        // a library that exists but disagrees on the signature is still an error
+                        LinkErrorKind::LibraryNotFound{..} | LinkErrorKind::ExportMissing{..} if weak => {
+                            (None, weak::stub(&store_, &expected, &module, &field, e.kind.to_string()))
+                        },
+                        _ => return Err(RuntimeError::user(Box::new(e)))
+                    }
+                };
+
+                *target.lock().unwrap() = Some((library, f.clone()));
+                f
+            }
+        };
+
+        f.call(args).map(|r|{r.into_vec()})
+    })
+}
@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::search;
+
+/// list of library directories, one per line.
+/// `include <path>` pulls in other files, `*` is allowed in the file name.
+/// `compiler <library> <backend>` compiles one library with another backend.
+pub const CONFIG_FILE:&str = "/etc/libwasm.conf";
+
+/// name to path index written by `libwasm ldconfig`
+pub const CACHE_FILE:&str = "/etc/libwasm.cache";
+
+const CACHE_HEADER:&str = "libwasm-cache-1";
+
+lazy_static::lazy_static!{
+    static ref CACHE:HashMap<String, PathBuf> = read_cache(Path::new(CACHE_FILE)).unwrap_or_default();
+}
+
+/// look up a library in the on-disk cache.
+/// stale entries pointing at removed files are ignored.
+pub fn lookup(name:&str) -> Option<PathBuf>{
+    CACHE.get(name).filter(|p|{p.is_file()}).cloned()
+}
+
+#[derive(Default)]
+struct Config{
+    directories:Vec<PathBuf>,
+    /// library name and compiler backend
+    compilers:Vec<(String, String)>
+}
+
+/// directories listed in the config file and its includes
+pub fn config_directories() -> Vec<PathBuf>{
+    let mut config = Config::default();
+    read_config(Path::new(CONFIG_FILE), &mut config, 0);
+    config.directories
+}
+
+/// per library compiler backends, later lines override earlier ones
+pub fn compiler_overrides() -> Vec<(String, String)>{
+    let mut config = Config::default();
+    read_config(Path::new(CONFIG_FILE), &mut config, 0);
+    config.compilers
+}
+
+fn read_config(path:&Path, config:&mut Config, depth:usize){
+    // guard against include loops
+    if depth > 8{
+        return;
+    }
+
+    let content = match std::fs::read_to_string(path){
+        Ok(c) => c,
+        Err(_) => return
+    };
+
+    for line in content.lines(){
+        let line = match line.find('#'){
+            Some(i) => &line[..i],
+            None => line
+        }.trim();
+
+        if line.is_empty(){
+            continue;
+        }
+
+        if let Some(pattern) = line.strip_prefix("include "){
+            for file in expand_include(pattern.trim(), path){
+                read_config(&file, config, depth + 1);
+            }
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("compiler "){
+            let mut parts = rest.split_whitespace();
+            if let (Some(name), Some(backend)) = (parts.next(), parts.next()){
+                config.compilers.push((name.to_string(), backend.to_string()));
+            }
+            continue;
+        }
+
+        let dir = PathBuf::from(line);
+        if !config.directories.contains(&dir){
+            config.directories.push(dir);
+        }
+    }
+}
+
+/// expand `dir/*.conf` style patterns, relative patterns are relative to the including file
+fn expand_include(pattern:&str, from:&Path) -> Vec<PathBuf>{
+    let mut pattern = PathBuf::from(pattern);
+    if pattern.is_relative(){
+        if let Some(parent) = from.parent(){
+            pattern = parent.join(pattern);
+        }
+    }
+
+    let file_name = pattern.file_name().and_then(|f|{f.to_str()}).unwrap_or("").to_owned();
+
+    let (prefix, suffix) = match file_name.split_once('*'){
+        Some(v) => v,
+        None => return vec![pattern]
+    };
+
+    let dir = match pattern.parent(){
+        Some(d) => d,
+        None => return Vec::new()
+    };
+
+    let mut files = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(dir){
+        for entry in entries.flatten(){
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            if name.len() >= prefix.len() + suffix.len() && name.starts_with(prefix) && name.ends_with(suffix){
+                files.push(entry.path());
+            }
+        }
+    }
+    // read in a predictable order
+    files.sort();
+    files
+}
+
+/// scan the configured and system directories, the first directory defining a name wins.
+pub fn scan() -> Vec<(String, PathBuf)>{
+    let mut dirs = config_directories();
+    for dir in search::system_directories(){
+        if !dirs.contains(&dir){
+            dirs.push(dir);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+
+    for dir in dirs{
+        let read = match std::fs::read_dir(&dir){
+            Ok(r) => r,
+            Err(_) => continue
+        };
+
+        let mut files = Vec::new();
+        for entry in read.flatten(){
+            let path = entry.path();
+            if path.is_file() && path.extension().map(|e|{e == "wasm"}).unwrap_or(false){
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        for path in files{
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if !seen.insert(name.clone()){
+                continue;
+            }
+
+            let path = path.canonicalize().unwrap_or(path);
+            entries.push((name, path));
+        }
+    }
+    entries
+}
+
+pub fn write_cache(path:&Path, entries:&[(String, PathBuf)]) -> std::io::Result<()>{
+    let mut content = String::new();
+    content.push_str(CACHE_HEADER);
+    content.push('\n');
+
+    for (name, lib) in entries{
+        content.push_str(name);
+        content.push('\t');
+        content.push_str(&lib.to_string_lossy());
+        content.push('\n');
+    }
+
+    // write then rename so a running loader never sees a half written cache
+    let tmp = path.with_extension("cache.tmp");
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)
+}
+
+pub fn read_cache(path:&Path) -> Option<HashMap<String, PathBuf>>{
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+
+    if lines.next()? != CACHE_HEADER{
+        return None
+    }
+
+    let mut map = HashMap::new();
+    for line in lines{
+        if let Some((name, lib)) = line.split_once('\t'){
+            map.insert(name.to_string(), PathBuf::from(lib));
+        }
+    }
+    Some(map)
+}
+
+/// `libwasm ldconfig [-p] [-v]`
+pub fn command(args:&[String]){
+    let print = args.iter().any(|a|{a == "-p" || a == "--print-cache"});
+    let verbose = args.iter().any(|a|{a == "-v" || a == "--verbose"});
+
+    if print{
+        let cache = read_cache(Path::new(CACHE_FILE)).unwrap_or_default();
+        let mut entries:Vec<_> = cache.into_iter().collect();
+        entries.sort();
+
+        println!("{} libs found in cache `{}'", entries.len(), CACHE_FILE);
+        for (name, path) in entries{
+            println!("      {} => {}", name, path.display());
+        }
+        return;
+    }
+
+    let entries = scan();
+
+    if verbose{
+        for (name, path) in &entries{
+            println!("      {} => {}", name, path.display());
+        }
+    }
+
+    if let Err(e) = write_cache(Path::new(CACHE_FILE), &entries){
+        eprintln!("libwasm: ldconfig: cannot write {}: {}", CACHE_FILE, e);
+        std::process::exit(1);
+    }
+}
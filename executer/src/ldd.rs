@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+
+use crate::graph::{Graph, Symbol};
+
+/// `libwasm ldd <executable.wasm>`
+/// prints the dependency tree with resolved paths, missing libraries and undefined symbols.
+pub fn command(graph:&Graph){
+    let root = &graph.nodes[0];
+    match &root.path{
+        Some(p) => println!("{} ({})", root.name, p.display()),
+        None => println!("{}", root.name)
+    }
+
+    let mut visited = HashSet::new();
+    visited.insert(0);
+    print_node(graph, 0, 1, &mut visited);
+}
+
+fn print_node(graph:&Graph, index:usize, depth:usize, visited:&mut HashSet<usize>){
+    let indent = "    ".repeat(depth);
+    let node = &graph.nodes[index];
+
+    for dep in &node.dependencies{
+        let i = match graph.find(dep){
+            Some(i) => i,
+            None => continue
+        };
+        let child = &graph.nodes[i];
+
+        match (&child.path, &child.error){
+            (Some(p), None) => println!("{}{} => {}", indent, dep, p.display()),
+            (Some(p), Some(e)) => println!("{}{} => {} ({})", indent, dep, p.display(), e),
+            (None, _) => println!("{}{} => not found", indent, dep)
+        }
+
+        // each library is expanded once, cycles and shared dependencies are listed by name only
+        if visited.insert(i){
+            print_node(graph, i, depth + 1, visited);
+        }
+    }
+
+    let module = match &node.module{
+        Some(m) => m,
+        None => return
+    };
+
+    for import in module.imports(){
+        let (m, field) = (import.module(), import.name());
+
+        // a missing library is already reported above
+        if let Symbol::Missing = graph.resolve(index, m, field){
+            let optional = if node.weak.contains(m, field){
+                " (optional)"
+            } else{
+                ""
+            };
+            println!("{}undefined symbol {}::{}{}", indent, m, field, optional);
+        }
+    }
+}
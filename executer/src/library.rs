@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, Weak};
+
+use wasmer::{Instance, Store};
+
+use crate::{dylink, got, scope};
+use crate::DEBUG;
+
+lazy_static::lazy_static!{
+    /// loaded libraries by content hash and name, an entry lives as long as some handle to it.
+    /// two files with the same name but different contents get separate entries.
+    static ref REGISTRY:RwLock<HashMap<(String, String), Weak<Library>>> = RwLock::new(HashMap::new());
+}
+
+/// a loaded module, shared through `Arc<Library>` handles.
+/// when the last handle is dropped the library runs its destructors
+/// and leaves the registry, the global scope and the offset table.
+pub struct Library{
+    pub name:String,
+    /// SHA-256 of the module file
+    pub hash:String,
+    pub instance:Instance,
+    /// libraries this one links against, kept loaded as long as it is
+    dependencies:Vec<Arc<Library>>,
+    placement:Option<dylink::Placement>,
+    store:Store,
+    is_main:bool
+}
+
+impl Library{
+    pub fn new(store:&Store, name:&str, hash:&str, instance:Instance, dependencies:Vec<Arc<Library>>, placement:Option<dylink::Placement>, is_main:bool) -> Arc<Self>{
+        Arc::new(Self {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            instance,
+            dependencies,
+            placement,
+            store: store.clone(),
+            is_main
+        })
+    }
+
+    fn key(&self) -> (String, String){
+        (self.hash.clone(), self.name.clone())
+    }
+}
+
+/// a handle to a loaded library with this name
+pub fn get(name:&str) -> Option<Arc<Library>>{
+    REGISTRY.read().unwrap().values().filter_map(|w|{w.upgrade()}).find(|l|{l.name == name})
+}
+
+/// a handle to a loaded library with this name and contents
+pub fn find(name:&str, hash:&str) -> Option<Arc<Library>>{
+    REGISTRY.read().unwrap().get(&(hash.to_string(), name.to_string())).and_then(|w|{w.upgrade()})
+}
+
+pub fn register(library:&Arc<Library>){
+    REGISTRY.write().unwrap().insert(library.key(), Arc::downgrade(library));
+}
+
+impl Drop for Library{
+    fn drop(&mut self){
+        if DEBUG{
+            println!("unloading {}.", self.name);
+        }
+
+        // the main module runs its own destructors on exit
+        if !self.is_main{
+            if let Ok(f) = self.instance.exports.get_function("__wasm_call_dtors"){
+                if let Err(e) = f.call(&[]){
+                    eprintln!("libwasm: destructors of {} trapped: {}", self.name, e);
+                }
+            }
+        }
+
+        {
+            // the entry may already belong to a reloaded copy
+            let key = self.key();
+            let mut registry = REGISTRY.write().unwrap();
+            if registry.get(&key).map(|w|{w.strong_count() == 0}).unwrap_or(false){
+                registry.remove(&key);
+            }
+        }
+
+        scope::remove(&self.name);
+        got::unregister(&self.store, &self.name);
+
+        if let Some(placement) = self.placement{
+            if let Ok(ctx) = dylink::context(&self.store){
+                ctx.release(placement);
+            }
+        }
+        dylink::remove_memory_base(&self.name);
+
+        // dependencies are released after this library's destructors have run
+        self.dependencies.clear();
+    }
+}
@@ -1,18 +1,51 @@
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::path::Path;
+use std::sync::Mutex;
 use std::sync::Arc;
 
 use wasmer::Exportable;
 use wasmer::ImportObject;
-use wasmer::{Store, Module, Instance,Value, Resolver};
+use wasmer::{Store, Module, Instance, Memory, Value, Resolver};
 use wasmer_emscripten::EmEnv;
 use wasmer_emscripten::EmscriptenGlobals;
 use wasmer_wasi::WasiEnv;
 
+use error::{LinkError, LinkErrorKind, EXIT_LINK_ERROR};
+use library::Library;
+
+mod argv;
+mod artifact;
+mod cache;
+mod check;
+mod compiler;
+mod dl;
+mod dylink;
+mod entry;
+mod error;
+mod exit;
+mod features;
+mod got;
+mod graph;
+mod lazy;
+mod ldconfig;
+mod ldd;
+mod library;
+mod pin;
+mod preload;
+mod scope;
+mod search;
+mod signing;
+mod weak;
+
 const DEBUG:bool = true;
 
 lazy_static::lazy_static!{
-   static ref LIBRARIES:RwLock<HashMap<String, Arc<Instance>>> = RwLock::new(HashMap::new()) ;
+   /// modules currently being loaded, the executable first
+   static ref LOADING:Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+   /// PIC libraries loaded while the executable is linked, in load order.
+   /// their relocations and constructors wait until its offset table entries are defined.
+   static ref STARTUP:Mutex<Option<Vec<Arc<Library>>>> = Mutex::new(Some(Vec::new()));
 }
 
 const HELP_DESCRIPTOR:&str = 
@@ -26,16 +59,54 @@ USAGE:
 OPTIONS:
     -ld <directory>             Add <directory> to the linker's search path
     -d, --debug                 Debug mode
+    --compiler <backend>        Compile with singlepass, cranelift or llvm, as built in (default cranelift)
+    --features-profile <name>   WebAssembly proposals to support: mvp, stable or all (default all)
+    --enable-<feature>          Enable one proposal on top of the profile, e.g. --enable-simd
+    --disable-<feature>         Disable one proposal, e.g. --disable-threads
+    --invoke <export>           Call <export> instead of main in modules without WASI or emscripten
+    --lazy                      Bind library functions on their first call
+    --preload <lib.wasm>        Resolve function imports from <lib.wasm> before any other library
+    --require-signed            Refuse modules without a valid signature from the keyring
+    --keyring <directory>       Trust the *.pub keys in <directory>, searched before /etc/libwasm/keys
+    --no-cache                  Compile every module instead of using $XDG_CACHE_HOME/libwasm
+    --warn-pins                 Load libraries whose SHA-256 differs from a libwasm.pins entry, with a warning
     -p, --inspect               Print wasm information
     -h, --help                  Print help information
 
 COMMANDS:
     install                     install package
+    compile <in.wasm> -o <out.wasmu>
+                                Precompile a module, libfoo.wasmu is used in place of the libfoo.wasm it was built from.
+                                --compiler chooses the backend, before or after the command
+    cache clean|stats           Remove or summarize cached compiled modules
+    ldconfig [-p] [-v]          Rebuild the library cache from /etc/libwasm.conf
+    sign <lib.wasm> --key <k>   Sign a module, --detached writes <lib.wasm>.sig instead
+    sign --keygen <name>        Generate <name>.key and <name>.pub
+    ldd                         Print the dependency tree and undefined symbols
+    check                       Verify every import of the executable and its libraries
     inspect                     Print wasm module information
     bindgen                     code generation linking wasm library
-"#;
 
-static mut SEARCH_PATHS:Vec<&str> = Vec::new();
+ENVIRONMENT:
+    LIBWASM_LIBRARY_PATH        Colon separated directories searched after -ld
+    LIBWASM_BIND_NOW            If set, bind every import at startup even with --lazy
+    LIBWASM_PRELOAD             Colon separated libraries loaded as if given with --preload
+
+LIBRARY SEARCH ORDER:
+    -ld directories, LIBWASM_LIBRARY_PATH, the executable's directory,
+    the current directory, /etc/libwasm.cache, $XDG_DATA_HOME/libwasm/lib,
+    /usr/local/lib/wasm, /usr/lib/wasm
+
+EXIT STATUS:
+    n                           The guest's proc_exit(n), exit(n) or the i32 returned by main
+    127                         The executable or a library could not be linked
+    128 + signal                The guest trapped, with the signal a native program would get:
+                                132 unreachable or bad indirect call signature (SIGILL),
+                                134 error in a host function (SIGABRT),
+                                135 misaligned access (SIGBUS),
+                                136 integer division by zero or overflow (SIGFPE),
+                                139 out of bounds access, null call or stack overflow (SIGSEGV)
+"#;
 
 fn main() {
     let mut wasm_executable = "".to_owned();
@@ -43,6 +114,13 @@ fn main() {
 
     let mut profiling = false;
     let mut inspect = false;
+    let mut list_dependencies = false;
+    let mut check_links = false;
+    let mut aot_compile = false;
+    let mut backend = compiler::Backend::preferred();
+    let mut features_profile = "all".to_owned();
+    let mut feature_flags = Vec::new();
+    let mut invoke = entry::DEFAULT_ENTRY.to_owned();
 
     let mut env_args = std::env::args();
 
@@ -55,12 +133,45 @@ fn main() {
 
         let arg = env_args.next().unwrap();
 
-        if i == 0{
+        // a command may follow the global options, only the first one is recognised
+        if !(inspect || aot_compile || list_dependencies || check_links){
             if arg == "install" {
                 todo!("bindgen command")
 
+            } else if arg == "ldconfig"{
+                let rest:Vec<String> = env_args.collect();
+                ldconfig::command(&rest);
+                return;
+
+            } else if arg == "cache"{
+                let rest:Vec<String> = env_args.collect();
+                cache::command(&rest);
+                return;
+
+            } else if arg == "sign"{
+                let rest:Vec<String> = env_args.collect();
+                signing::command(&rest);
+                return;
+
             } else if arg == "inspect"{
                 inspect = true;
+                i+=1;
+                continue;
+
+            } else if arg == "compile"{
+                aot_compile = true;
+                i+=1;
+                continue;
+
+            } else if arg == "ldd"{
+                list_dependencies = true;
+                i+=1;
+                continue;
+
+            } else if arg == "check"{
+                check_links = true;
+                i+=1;
+                continue;
 
             } else if arg == "bindgen"{
                 todo!("bindgen command")
@@ -81,12 +192,59 @@ fn main() {
 
         } else if arg == "-d" || arg == "--debug"{
 
+        } else if arg == "--lazy"{
+            lazy::enable();
+
+        } else if arg == "--require-signed"{
+            signing::require_signed();
+
+        } else if arg == "--compiler"{
+            i+=1;
+            let name = env_args.next().expect("missing <backend> for --compiler flag");
+
+            backend = compiler::Backend::parse(&name).unwrap_or_else(|e|{
+                eprintln!("libwasm: {}", e);
+                std::process::exit(2);
+            });
+
+        } else if arg == "--features-profile"{
+            i+=1;
+            features_profile = env_args.next().expect("missing <name> for --features-profile flag");
+
+        } else if let Some(name) = arg.strip_prefix("--enable-"){
+            feature_flags.push((name.to_string(), true));
+
+        } else if let Some(name) = arg.strip_prefix("--disable-"){
+            feature_flags.push((name.to_string(), false));
+
+        } else if arg == "--invoke"{
+            i+=1;
+            invoke = env_args.next().expect("missing <export> for --invoke flag");
+
+        } else if arg == "--no-cache"{
+            cache::disable();
+
+        } else if arg == "--warn-pins"{
+            pin::warn_only();
+
+        } else if arg == "--keyring"{
+            i+=1;
+            let dir = env_args.next().expect("missing <directory> for --keyring flag");
+
+            signing::add_keyring(&dir);
+
+        } else if arg == "--preload"{
+            i+=1;
+            let lib = env_args.next().expect("missing <lib.wasm> for --preload flag");
+
+            preload::add(&lib);
+
         } else if arg == "-ld" || arg == "--link-directory"{
             // the next argument is directory
             i+=1;
             let dir = env_args.next().expect("missing <directory> for -ld flag");
 
-            unsafe{SEARCH_PATHS.push(Box::leak(Box::new(dir)))}
+            search::add_link_directory(&dir);
 
         } else{
             wasm_executable = arg;
@@ -102,26 +260,34 @@ fn main() {
         panic!("libwasm: fatal error: no input files.")
     }
 
-    let config = wasmer::Cranelift::new();
-    let engine = wasmer::Universal::new(config);
-    let engine = engine.features(
-        wasmer::Features { 
-            threads: true, 
-            reference_types: true, 
-            simd: true, 
-            bulk_memory: true, 
-            multi_value: true, 
-            tail_call: true, 
-            module_linking: false, 
-            multi_memory: true, 
-            memory64: true, 
-            exceptions: true, 
-            relaxed_simd: true, 
-            extended_const: true 
-        }).engine();
-
-    let store = Store::new(&engine);
-    let module = Module::from_file(&store, wasm_executable).unwrap();
+    search::set_executable(&wasm_executable);
+
+    // single proposals are switched after the profile, whatever their order on the command line
+    let features = features::profile(&features_profile).and_then(|mut f|{
+        for (name, enabled) in &feature_flags{
+            features::set(&mut f, name, *enabled)?;
+        }
+        Ok(f)
+    }).unwrap_or_else(|e|{
+        eprintln!("libwasm: {}", e);
+        std::process::exit(2);
+    });
+
+    let store = compiler::init(backend, &features);
+    cache::set_identity(&store, &features);
+
+    if aot_compile{
+        artifact::command(&store, backend, Path::new(&wasm_executable), &args);
+        return;
+    }
+
+    let bytes = read_module(Path::new(&wasm_executable)).unwrap_or_else(|e|{fatal(e)});
+    let main_hash = pin::hash(&bytes);
+    let module = compile(&store, &wasm_executable, backend, Path::new(&wasm_executable), &bytes, &main_hash).unwrap_or_else(|e|{fatal(e)});
+    drop(bytes);
+
+    // the executable's pins take precedence over those of its libraries
+    pin::record(&module);
 
 
     // print the profiled information and exit
@@ -141,132 +307,474 @@ fn main() {
         return;
     }
 
-    let mut resolver = CombindedResolver::new();
+    if list_dependencies || check_links{
+        let name = module.name().unwrap_or("main").to_string();
+        let graph = graph::Graph::build(&store, &module, &name, Path::new(&wasm_executable));
+
+        if list_dependencies{
+            ldd::command(&graph);
+        } else{
+            check::command(&graph);
+        }
+        return;
+    }
+
+    let mut resolver = CombindedResolver::new(&store, &module);
+    let main_name = module.name().unwrap_or("main").to_string();
+
+    LOADING.lock().unwrap().push(main_name.clone());
+
+    // the executable's symbols come first in the global scope, as in ELF
+    scope::declare_executable(&main_name, &module);
+
+    dylink::init(&module);
+    if let Some(info) = dylink::parse(&module){
+        resolver.enable_dylink(&main_name, &info).unwrap_or_else(|e|{fatal(e)});
+    }
+    load_preload(&store).unwrap_or_else(|e|{fatal(e)});
+    resolver.load_needed(&module).unwrap_or_else(|e|{fatal(e)});
 
     // parse arguments
 
+    // the library keeps everything the executable links against loaded until it returns
+    let (library, status) =
+
     if wasmer_wasi::is_wasi_module(&module){
-        resolver.enable_wasi(module.name().unwrap_or("main"), &module, &args);
+        resolver.enable_wasi(&main_name, &module, &args).unwrap_or_else(|e|{fatal(e)});
+
+        let instance = instantiate(&module, &resolver, &main_name).unwrap_or_else(|e|{fatal(e)});
 
-        let instance = Instance::new(&module, &resolver).unwrap();
+        let library = resolver.finish(&main_name, &main_hash, &instance, true).unwrap_or_else(|e|{fatal(e)});
 
         if DEBUG{
             println!("all dependency resolved, run _start function.");
         }
 
-        let start = instance.exports.get_function("_start").unwrap();
-        start.call(&[]).unwrap();
+        let start = instance.exports.get_function("_start").unwrap_or_else(|_|{
+            eprintln!("libwasm: {} does not export _start", main_name);
+            std::process::exit(EXIT_LINK_ERROR);
+        });
+
+        // proc_exit unwinds out of _start as an error carrying the exit code
+        let status = match start.call(&[]){
+            Ok(_) => 0,
+            Err(e) => exit::status(e)
+        };
+        (library, status)
 
     } else if wasmer_emscripten::is_emscripten_module(&module){
-        let (mut env, mut globals) = resolver.enable_emscripten(&module);
+        let (mut env, globals) = resolver.enable_emscripten(&main_name, &module).unwrap_or_else(|e|{fatal(e)});
+
+        let mut instance = instantiate(&module, &resolver, &main_name).unwrap_or_else(|e|{fatal(e)});
+
+        let library = resolver.finish(&main_name, &main_hash, &instance, true).unwrap_or_else(|e|{fatal(e)});
+
+        env.set_memory(globals.memory.clone());
+        wasmer_emscripten::set_up_emscripten(&mut instance).unwrap_or_else(|e|{
+            fatal(instantiate_error(&main_name, e.to_string()))
+        });
+
+        // emscripten's exit(n) leaves the process itself, otherwise main's return value is the status.
+        // older toolchains prefix the C name with an underscore
+        let entry = if instance.exports.get_function("_main").is_ok(){ "_main" } else{ "main" };
+        let status = run_entry(&store, &instance, Some(&globals.memory), &main_name, entry, &wasm_executable, &args);
+        (library, status)
+
+    } else{
+
+        let instance = instantiate(&module, &resolver, &main_name).unwrap_or_else(|e|{fatal(e)});
+
+        let library = resolver.finish(&main_name, &main_hash, &instance, true).unwrap_or_else(|e|{fatal(e)});
+
+        let status = run_entry(&store, &instance, None, &main_name, &invoke, &wasm_executable, &args);
+        (library, status)
+    };
+
+    // libraries run their destructors before the process exits
+    drop(library);
+    std::process::exit(status);
+}
+
+/// call the entry point of the executable and return the exit status.
+/// `program` is passed as argv[0] when the entry point takes arguments,
+/// `memory` is the guest's memory when the host provides it.
+fn run_entry(store:&Store, instance:&Instance, memory:Option<&Memory>, name:&str, export:&str, program:&str, args:&[&str]) -> i32{
+    let main = instance.exports.get_function(export).unwrap_or_else(|_|{
+        eprintln!("libwasm: {} does not export a function named {}", name, export);
+        std::process::exit(EXIT_LINK_ERROR);
+    });
+
+    let signature = entry::detect(main).unwrap_or_else(|e|{
+        eprintln!("libwasm: cannot call {}: {}", export, e);
+        std::process::exit(EXIT_LINK_ERROR);
+    });
+
+    let params = match signature{
+        entry::Signature::Argv(pointer) => {
+            // argv[0] is the program, as in C
+            let mut argv_strings = vec![program];
+            argv_strings.extend(args.iter());
+
+            let argv = argv::write(store, instance, memory, &argv_strings, pointer).unwrap_or_else(|e|{
+                eprintln!("libwasm: cannot pass arguments: {}", e);
+                std::process::exit(EXIT_LINK_ERROR);
+            });
+            vec![Value::I32(argv.argc), argv.argv]
+        },
+        _ => Vec::new()
+    };
+
+    // the returned i32 is the exit status, () -> () exits with 0
+    match main.call(&params){
+        Ok(result) => match result.first(){
+            Some(Value::I32(status)) => *status,
+            _ => 0
+        },
+        Err(e) => exit::status(e)
+    }
+}
 
-        let mut instance = Instance::new(&module, &resolver).unwrap();
+/// print a link error as a one line diagnostic and exit
+fn fatal(e:LinkError) -> !{
+    eprintln!("libwasm: link error: {}", e);
+    std::process::exit(EXIT_LINK_ERROR);
+}
 
-        wasmer_emscripten::run_emscripten_instance(&mut instance, &mut env, &mut globals, "./", args, None).unwrap();
+/// a link error annotated with the modules currently being loaded
+fn link_error(kind:LinkErrorKind) -> LinkError{
+    LinkError::new(kind, LOADING.lock().unwrap().clone())
+}
 
+/// read a module file, checking its signature when signatures are required.
+/// the bytes that are verified are the bytes that get hashed and compiled.
+fn read_module(path:&Path) -> Result<Vec<u8>, LinkError>{
+    let bytes = std::fs::read(path).map_err(|e|{
+        link_error(LinkErrorKind::Compile{
+            path: path.display().to_string(),
+            message: e.to_string()
+        })
+    })?;
+
+    if signing::is_required(){
+        signing::verify(path, &bytes).map_err(|message|{
+            link_error(LinkErrorKind::Untrusted{
+                path: path.display().to_string(),
+                message
+            })
+        })?;
+    }
+    Ok(bytes)
+}
+
+/// compile a module with `backend` or load it from the cache, `hash` is its content hash.
+/// artifacts are loaded as they are, a precompiled sibling of library `name` only when `artifact::sibling` trusts it.
+fn compile(store:&Store, name:&str, backend:compiler::Backend, path:&Path, bytes:&[u8], hash:&str) -> Result<Module, LinkError>{
+    let compile_error = |message:String|{
+        link_error(LinkErrorKind::Compile{
+            path: path.display().to_string(),
+            message
+        })
+    };
+
+    let sibling = artifact::sibling(path, name, backend, hash).and_then(|a|{artifact::load(store, backend, &a).ok()});
+
+    let mut module = if artifact::is_artifact(bytes){
+        artifact::load(store, backend, bytes).map_err(compile_error)?
+    } else if let Some(m) = sibling{
+        m
     } else{
+        match cache::load(store, backend, hash){
+            Some(m) => m,
+            None => {
+                let module = compiler::compile(store, backend, bytes).map_err(|e|{compile_error(features::explain(&e))})?;
+                cache::store(&module, backend, hash);
+                module
+            }
+        }
+    };
 
-        let instance = Instance::new(&module, &resolver).unwrap();
+    // same name Module::from_file would give it
+    if let Ok(canonical) = path.canonicalize(){
+        module.set_name(&canonical.display().to_string());
+    }
+    Ok(module)
+}
 
-        let main = instance.exports.get_function("main").expect("cannot find function main");
+/// read a library file and check it against the pin for its name, returns its bytes and content hash
+fn read_library(name:&str, path:&Path) -> Result<(Vec<u8>, String), LinkError>{
+    let bytes = read_module(path)?;
+    let hash = pin::hash(&bytes);
 
-        let argc = args.len() as i32;
-        let argv = args.as_ptr() as i64;
+    if let Some(expected) = pin::mismatch(name, &hash){
+        let e = link_error(LinkErrorKind::HashMismatch{
+            path: path.display().to_string(),
+            expected,
+            found: hash.clone()
+        });
 
-        main.call(&[Value::I32(argc), Value::I64(argv)]).unwrap();
+        if !pin::is_warn_only(){
+            return Err(e)
+        }
+        eprintln!("libwasm: warning: {}", e);
     }
+    Ok((bytes, hash))
 }
 
-fn resolve_import(name:&str) -> (String, Arc<Instance>){
+/// compile a library with the backend configured for it
+fn compile_library(store:&Store, name:&str, path:&Path, bytes:&[u8], hash:&str) -> Result<Module, LinkError>{
+    compile(store, name, compiler::for_library(name), path, bytes, hash)
+}
+
+/// a module could be compiled but not set up or started
+fn instantiate_error(module:&str, message:String) -> LinkError{
+    link_error(LinkErrorKind::Instantiate{
+        module: module.to_string(),
+        message
+    })
+}
+
+/// instantiate a module, reporting the first resolution failure recorded by the resolver
+fn instantiate(module:&Module, resolver:&CombindedResolver, name:&str) -> Result<Instance, LinkError>{
+    Instance::new(module, resolver).map_err(|e|{
+        resolver.take_error().unwrap_or_else(||{instantiate_error(name, e.to_string())})
+    })
+}
+
+/// libraries are compiled with the executable's engine and live in its store,
+/// so exports can be shared between instances.
+fn resolve_import(store:&Store, name:&str) -> Result<Arc<Library>, LinkError>{
 
     if DEBUG{
         println!("resolving module: {}", name);
     }
 
-    let lib = LIBRARIES.read().unwrap();
+    if let Some(a) = library::get(name){
+        // a loaded copy that does not match the pin is not reused
+        if pin::mismatch(name, &a.hash).is_none(){
 
-    if let Some(a) = lib.get(name){
+            if DEBUG{
+                println!("symbol {} already resolved, load from instance.", name);
+            }
+            return Ok(a)
+        }
+    }
 
-        if DEBUG{
-            println!("symbol {} already resolved, load from instance.", name);
+    {
+        let loading = LOADING.lock().unwrap();
+        if let Some(pos) = loading.iter().position(|n|{n == name}){
+            let mut cycle = loading[pos..].to_vec();
+            cycle.push(name.to_string());
+            drop(loading);
+
+            return Err(link_error(LinkErrorKind::CyclicDependency{cycle}))
         }
-        return (name.to_string(), a.clone())
+    }
 
-    } else{
-        for i in std::fs::read_dir(std::env::current_dir().unwrap()).unwrap(){
-            if let Ok(v) = i{
+    let path = match search::find_library(name){
+        Some(p) => p,
+        None => return Err(link_error(LinkErrorKind::LibraryNotFound{name: name.to_string()}))
+    };
 
-                let path = v.path().canonicalize().unwrap();
+    if DEBUG{
+        println!("library {} found at {}", name, path.as_path().to_str().unwrap())
+    }
 
-                if path.is_file() && path.file_name().unwrap() == name{
+    let (bytes, hash) = read_library(name, &path)?;
 
-                    if DEBUG{
-                        println!("library {} found at {}", name, path.as_path().to_str().unwrap())
-                    }
+    // the same contents may already be loaded under this name
+    if let Some(a) = library::find(name, &hash){
+        return Ok(a)
+    }
 
-                    let store = Store::default();
-                    let module = Module::from_file(&store, path).unwrap();
-
-                    let mut resolver = CombindedResolver::new();
-
-                    let instance = 
-
-                    if wasmer_wasi::is_wasi_module(&module){
-                        resolver.enable_wasi(module.name().unwrap_or("main"), &module, &[]);
-                
-                        let instance = Instance::new(&module, &resolver).unwrap();
-                
-                        if DEBUG{
-                            println!("module {} is loaded and ready.", name);
-                        }
-                        Arc::new(instance)
-                
-                    } else if wasmer_emscripten::is_emscripten_module(&module){
-                        let (mut env, globals) = resolver.enable_emscripten(&module);
-                
-                        let mut instance = Instance::new(&module, &resolver).unwrap();
-                
-                        env.set_memory(globals.memory.clone());
-                        wasmer_emscripten::set_up_emscripten(&mut instance).unwrap();
-                
-                        Arc::new(instance)
-                    } else{
-                
-                        let instance = Instance::new(&module, &resolver).unwrap();
-
-                        Arc::new(instance)
-                    };
-
-                    drop(lib);
-                    LIBRARIES.write().unwrap().insert(name.to_string(), instance.clone());
-
-                    return (name.to_string(), instance)
-                }
-            }
+    LOADING.lock().unwrap().push(name.to_string());
+    let result = load_library(store, name, &path, &bytes, &hash);
+    LOADING.lock().unwrap().pop();
+
+    let library = result?;
+    library::register(&library);
+
+    Ok(library)
+}
+
+/// load the preload libraries ahead of everything else, they also come first in the global scope
+fn load_preload(store:&Store) -> Result<(), LinkError>{
+    let names = preload::requested();
+    scope::declare(&names);
+
+    let mut libraries = Vec::new();
+    for name in &names{
+        libraries.push(resolve_import(store, name)?);
+    }
+
+    preload::activate(libraries);
+    Ok(())
+}
+
+/// find a symbol in the global scope, loading declared libraries in order until one defines it
+fn scope_lookup(store:&Store, field:&str) -> Result<(Arc<Library>, wasmer::Extern), LinkError>{
+    if let Some(v) = scope::lookup(field){
+        return Ok(v)
+    }
+
+    for name in scope::pending(){
+        resolve_import(store, &name)?;
+
+        if let Some(v) = scope::lookup(field){
+            return Ok(v)
         }
+    }
+
+    Err(link_error(LinkErrorKind::ExportMissing{
+        module: scope::SCOPE_MODULE.to_string(),
+        field: field.to_string()
+    }))
+}
+
+/// complete the link once the executable is instantiated and in the global scope.
+/// imports deferred to it are checked, then every library loaded so far is relocated
+/// before any constructor runs, dependencies first.
+fn link_executable(store:&Store, main:&Arc<Library>, is_pic:bool) -> Result<(), LinkError>{
+    for (module, field, ty) in lazy::take_deferred(){
+        bind_function(store, &module, &field, &ty)?;
+    }
+
+    // relocations read the offset table, so it must be complete before they run
+    check_got(store)?;
+
+    // libraries loaded by check_got joined the list above
+    let libraries = STARTUP.lock().unwrap().take().unwrap_or_default();
+
+    for library in &libraries{
+        dylink::apply_relocations(&library.instance).map_err(|message|{instantiate_error(&library.name, message)})?;
+    }
+    if is_pic{
+        dylink::apply_relocations(&main.instance).map_err(|message|{instantiate_error(&main.name, message)})?;
+    }
+    for library in &libraries{
+        dylink::call_ctors(&library.instance).map_err(|message|{instantiate_error(&library.name, message)})?;
+    }
+    Ok(())
+}
+
+/// fail on offset table entries that nothing defines once the executable is linked.
+/// libraries not loaded yet in lazy mode are loaded first, data addresses cannot be bound lazily.
+fn check_got(store:&Store) -> Result<(), LinkError>{
+    for (_, field) in got::undefined(){
+        let _ = scope_lookup(store, &field);
+    }
+
+    match got::undefined().into_iter().next(){
+        Some((module, field)) => Err(link_error(LinkErrorKind::ExportMissing{module, field})),
+        None => Ok(())
+    }
+}
+
+/// resolve a function import when a lazy trampoline is first called.
+/// the handle keeps the defining library loaded for as long as the trampoline uses it.
+fn bind_function(store:&Store, module:&str, field:&str, expected:&wasmer::FunctionType) -> Result<(Arc<Library>, wasmer::Function), LinkError>{
+    let (library, ext) = if module == scope::SCOPE_MODULE{
+        scope_lookup(store, field)?
+    } else{
+        let library = resolve_import(store, module)?;
+        let ext = library.instance.exports.get_extern(field).cloned().ok_or_else(||{
+            link_error(LinkErrorKind::ExportMissing{
+                module: module.to_string(),
+                field: field.to_string()
+            })
+        })?;
+        (library, ext)
+    };
+
+    let expected = wasmer::ExternType::Function(expected.clone());
+    let found = ext.ty();
+
+    match ext{
+        wasmer::Extern::Function(f) if types_compatible(&expected, &found) => Ok((library, f)),
+        _ => Err(link_error(LinkErrorKind::TypeMismatch{
+            module: module.to_string(),
+            field: field.to_string(),
+            expected: format_ty(&expected),
+            found: format_ty(&found)
+        }))
+    }
+}
+
+fn load_library(store:&Store, name:&str, path:&Path, bytes:&[u8], hash:&str) -> Result<Arc<Library>, LinkError>{
+    let module = compile_library(store, name, path, bytes, hash)?;
+    pin::record(&module);
+
+    let mut resolver = CombindedResolver::new(store, &module);
 
-        panic!("unable to resolve symbol '{}'", name);
+    if let Some(info) = dylink::parse(&module){
+        if DEBUG{
+            println!("{} is a position independent side module.", name);
+        }
+        resolver.enable_dylink(name, &info)?;
     }
+    resolver.load_needed(&module)?;
+
+    let instance = 
+
+    if wasmer_wasi::is_wasi_module(&module){
+        resolver.enable_wasi(module.name().unwrap_or("main"), &module, &[])?;
+
+        let instance = instantiate(&module, &resolver, name)?;
+
+        if DEBUG{
+            println!("module {} is loaded and ready.", name);
+        }
+        instance
+
+    } else if wasmer_emscripten::is_emscripten_module(&module){
+        let (mut env, globals) = resolver.enable_emscripten(name, &module)?;
+
+        let mut instance = instantiate(&module, &resolver, name)?;
+
+        env.set_memory(globals.memory.clone());
+        wasmer_emscripten::set_up_emscripten(&mut instance).map_err(|e|{instantiate_error(name, e.to_string())})?;
+
+        instance
+    } else{
+
+        instantiate(&module, &resolver, name)?
+    };
+
+    resolver.finish(name, hash, &instance, false)
 }
 
 pub struct CombindedResolver{
-    modules:Vec<(String, Arc<Instance>)>,
+    store:Store,
+    /// libraries this module links against, they become dependencies of its Library
+    modules:Mutex<Vec<Arc<Library>>>,
     env:Option<ImportObject>,
+    /// where the data and table slots of a PIC module were placed
+    placement:Option<dylink::Placement>,
+    /// the first failure while resolving imports, wasmer only sees a missing import
+    error:Mutex<Option<LinkError>>,
+    /// expected type of each import, by import index
+    import_types:Vec<wasmer::ExternType>,
+    /// imports that may be missing
+    weak:weak::WeakImports,
     is_wasi:bool,
     is_emscripten:bool
 }
 
 impl CombindedResolver{
-    fn new() -> Self{
+    fn new(store:&Store, module:&Module) -> Self{
         return Self { 
-            modules: Vec::new(), 
+            store: store.clone(),
+            modules: Mutex::new(Vec::new()),
             env: None, 
+            placement: None,
+            error: Mutex::new(None),
+            import_types: module.imports().map(|i|{i.ty().clone()}).collect(),
+            weak: weak::WeakImports::parse(module),
             is_wasi: false, 
             is_emscripten: false 
         }
     }
 
-    fn enable_wasi(&mut self, name:&str, module:&Module, args:&[&str]) -> WasiEnv{
+    fn enable_wasi(&mut self, name:&str, module:&Module, args:&[&str]) -> Result<WasiEnv, LinkError>{
 
         if DEBUG{
             println!("wasi environment enabled for {}", name);
@@ -274,45 +782,228 @@ impl CombindedResolver{
 
         let mut env = wasmer_wasi::WasiState::new(name)
         .args(args)
-        .finalize().unwrap();
+        .finalize().map_err(|e|{instantiate_error(name, e.to_string())})?;
 
-        self.env = Some(env.import_object(module).unwrap());
+        self.env = Some(env.import_object(module).map_err(|e|{instantiate_error(name, e.to_string())})?);
         self.is_wasi = true;
-        env
+        Ok(env)
+    }
+
+    fn enable_dylink(&mut self, name:&str, info:&dylink::DylinkInfo) -> Result<(), LinkError>{
+        let placement = dylink::context(&self.store)
+            .and_then(|ctx|{ctx.allocate(info)})
+            .map_err(|message|{instantiate_error(name, message)})?;
+
+        if DEBUG{
+            println!("dylink: memory base {}, table base {}.", placement.memory_base, placement.table_base);
+        }
+        self.placement = Some(placement);
+        Ok(())
+    }
+
+    /// load the libraries a module declares as needed, in order,
+    /// so their symbols are in the global scope before it is instantiated.
+    fn load_needed(&mut self, module:&Module) -> Result<(), LinkError>{
+        let needed = scope::needed(module);
+        scope::declare(&needed);
+
+        // loaded on demand by scope_lookup
+        if lazy::enabled(){
+            return Ok(())
+        }
+
+        for name in &needed{
+            let lib = resolve_import(&self.store, name)?;
+            self.hold(lib);
+        }
+        Ok(())
     }
 
-    fn enable_emscripten(&mut self, module:&Module) -> (EmEnv, EmscriptenGlobals){
-        let mut globals = wasmer_emscripten::EmscriptenGlobals::new(module.store(), module).unwrap();
+    /// keep a library loaded for as long as the module being linked
+    fn hold(&self, lib:Arc<Library>){
+        let mut modules = self.modules.lock().unwrap();
+        if modules.iter().any(|l|{l.name == lib.name}){
+            return;
+        }
+        modules.push(lib);
+    }
+
+    /// publish the exports of a new instance to the global scope and offset table,
+    /// then apply its relocations and run its constructors if it is position independent.
+    /// the returned handle owns the libraries the module was linked against.
+    fn finish(self, name:&str, hash:&str, instance:&Instance, is_main:bool) -> Result<Arc<Library>, LinkError>{
+        let is_pic = self.placement.is_some();
+        let library = Library::new(&self.store, name, hash, instance.clone(), self.modules.into_inner().unwrap(), self.placement, is_main);
+        scope::add(&library);
+
+        let memory_base = self.placement.map(|p|{p.memory_base}).unwrap_or(0);
+        dylink::set_memory_base(name, memory_base);
+        got::register(&self.store, name, instance, memory_base);
+
+        if is_main{
+            link_executable(&self.store, &library, is_pic)?;
+            return Ok(library)
+        }
+
+        if !is_pic{
+            return Ok(library)
+        }
+
+        // the executable's entries in the offset table are still null
+        if let Some(startup) = STARTUP.lock().unwrap().as_mut(){
+            startup.push(library.clone());
+            return Ok(library)
+        }
+
+        // a failure drops the library again, which undoes the registration above
+        dylink::apply_relocations(instance).map_err(|message|{instantiate_error(name, message)})?;
+        dylink::call_ctors(instance).map_err(|message|{instantiate_error(name, message)})?;
+        Ok(library)
+    }
+
+    fn enable_emscripten(&mut self, name:&str, module:&Module) -> Result<(EmEnv, EmscriptenGlobals), LinkError>{
+        let mut globals = wasmer_emscripten::EmscriptenGlobals::new(module.store(), module)
+            .map_err(|message|{instantiate_error(name, message)})?;
         let env = wasmer_emscripten::EmEnv::new(&globals.data, HashMap::new());
         let obj = wasmer_emscripten::generate_emscripten_env(module.store(), &mut globals, &env);
         self.env = Some(obj);
         self.is_emscripten = true;
-        (env, globals)
+        Ok((env, globals))
+    }
+
+    /// record a resolution failure, only the first one is kept
+    fn fail(&self, e:LinkError){
+        let mut error = self.error.lock().unwrap();
+        if error.is_none(){
+            *error = Some(e);
+        }
+    }
+
+    fn take_error(&self) -> Option<LinkError>{
+        self.error.lock().unwrap().take()
+    }
+
+    /// check a library export against the import it is about to satisfy,
+    /// so ABI drift is reported with both signatures instead of failing inside wasmer.
+    fn check_type(&self, index:u32, module:&str, field:&str, ext:&wasmer::Extern) -> Result<(), LinkError>{
+        let expected = match self.import_types.get(index as usize){
+            Some(t) => t,
+            None => return Ok(())
+        };
+        let found = ext.ty();
+
+        if types_compatible(expected, &found){
+            return Ok(())
+        }
+
+        Err(link_error(LinkErrorKind::TypeMismatch{
+            module: module.to_string(),
+            field: field.to_string(),
+            expected: format_ty(expected),
+            found: format_ty(&found)
+        }))
     }
 }
 
-impl Resolver for CombindedResolver{
-    fn resolve(&self, index: u32, module: &str, field: &str) -> Option<wasmer::Export> {
-        
+impl CombindedResolver{
+    /// find the export satisfying an import
+    fn link(&self, index: u32, module: &str, field: &str) -> Result<wasmer::Export, LinkError>{
+
         if let Some(env) = &self.env{
             if let Some(v) = env.resolve(index, module, field){
 
                 if DEBUG{
                     println!("{}.{} resolved from environment.", module, field);
                 }
-                return Some(v)
+                return Ok(v)
             };
         }
+
+        let missing = ||{
+            link_error(LinkErrorKind::ExportMissing{
+                module: module.to_string(),
+                field: field.to_string()
+            })
+        };
         
-        for (name, instance) in &self.modules{
-            if module == name{
-                if let Some(ext) = instance.exports.get_extern(field){
+        if got::is_got_module(module){
+            return got::resolve(&self.store, module, field, self.weak.contains(module, field)).ok_or_else(missing)
+        }
+
+        if module == dl::DL_MODULE{
+            return dl::resolve(&self.store, field).ok_or_else(missing)
+        }
+
+        if module == "env" && dylink::IMPORTS.contains(&field){
+            let ctx = dylink::context(&self.store).map_err(|message|{
+                instantiate_error(&LOADING.lock().unwrap().last().cloned().unwrap_or_default(), message)
+            })?;
+            if let Some(v) = ctx.resolve(&self.store, self.placement, field){
+
+                if DEBUG{
+                    println!("{}.{} resolved from dynamic linker.", module, field);
+                }
+                return Ok(v)
+            }
+        }
+
+        if let Some(wasmer::ExternType::Function(_)) = self.import_types.get(index as usize){
+            if let Some((name, ext)) = preload::lookup(field){
+                if self.check_type(index, module, field, &ext).is_ok(){
+
+                    if DEBUG{
+                        println!("{}.{} interposed by preloaded {}.", module, field, name);
+                    }
+                    return Ok(ext.to_export())
+                }
+            }
+        }
+
+        // the executable's definitions come first but it is instantiated last,
+        // its functions are reached through trampolines until then
+        if module == scope::SCOPE_MODULE && scope::defined_by_executable(field){
+            if let Some(wasmer::ExternType::Function(ty)) = self.import_types.get(index as usize){
+
+                if DEBUG{
+                    println!("{}.{} is defined by the executable, bound to a trampoline.", module, field);
+                }
+                let f = lazy::defer(&self.store, ty, module, field);
+                return Ok(wasmer::Extern::Function(f).to_export())
+            }
+        }
+
+        if lazy::enabled() && !self.is_bound(module, field){
+            if let Some(wasmer::ExternType::Function(ty)) = self.import_types.get(index as usize){
+
+                if DEBUG{
+                    println!("{}.{} bound to a lazy trampoline.", module, field);
+                }
+                let f = lazy::trampoline(&self.store, ty, module, field, self.weak.contains(module, field));
+                return Ok(wasmer::Extern::Function(f).to_export())
+            }
+        }
+
+        if module == scope::SCOPE_MODULE{
+            let (lib, ext) = scope_lookup(&self.store, field)?;
+            self.check_type(index, module, field, &ext)?;
+
+            if DEBUG{
+                println!("{}.{} resolved from {} in global scope.", module, field, lib.name);
+            }
+            self.hold(lib);
+            return Ok(ext.to_export())
+        }
+
+        for lib in self.modules.lock().unwrap().iter(){
+            if module == lib.name{
+                if let Some(ext) = lib.instance.exports.get_extern(field){
+                    self.check_type(index, module, field, ext)?;
 
                     if DEBUG{
                         println!("{}.{} resolved from module.", module, field);
                     }
 
-                    return Some(ext.to_export())
+                    return Ok(ext.to_export())
                 }
             }
         };
@@ -321,18 +1012,90 @@ impl Resolver for CombindedResolver{
             println!("{}.{} not loaded, resolving dynamically.", module, field);
         }
 
-        let (name, instance) = resolve_import(module);
-        if let Some(ext) = instance.exports.get_extern(field){
-            unsafe{((&self.modules) as *const _ as *mut Vec<(String, Arc<Instance>)>).as_mut().unwrap().push((name, instance.clone()))};
-            return Some(ext.to_export())
+        let lib = resolve_import(&self.store, module)?;
+        if let Some(ext) = lib.instance.exports.get_extern(field).cloned(){
+            self.check_type(index, module, field, &ext)?;
+            self.hold(lib);
+            return Ok(ext.to_export())
+        }
+        if DEBUG{
+            println!("failed to resolve {} from {}.", field, lib.name);
+        }
+        Err(missing())
+    }
+
+    /// whether an import can be satisfied without loading another library
+    fn is_bound(&self, module:&str, field:&str) -> bool{
+        if module == scope::SCOPE_MODULE{
+            return scope::lookup(field).is_some()
         }
+        library::get(module).is_some()
+    }
+
+    /// a trapping stub for an optional function import that could not be found
+    fn weak_stub(&self, index:u32, module:&str, field:&str, e:&LinkError) -> Option<wasmer::Export>{
+        if !self.weak.contains(module, field){
+            return None
+        }
+
+        // a library that exists but disagrees on the signature is still an error
+        match e.kind{
+            LinkErrorKind::LibraryNotFound{..} | LinkErrorKind::ExportMissing{..} => {},
+            _ => return None
+        }
+
+        let ty = match self.import_types.get(index as usize){
+            Some(wasmer::ExternType::Function(f)) => f,
+            _ => return None
+        };
+
         if DEBUG{
-            println!("failed to resolve {} from {}.", field, name);
+            println!("{}.{} is optional and missing, bound to a trapping stub.", module, field);
         }
-        None
+
+        let stub = weak::stub(&self.store, ty, module, field, e.kind.to_string());
+        Some(wasmer::Extern::Function(stub).to_export())
     }
 }
 
+impl Resolver for CombindedResolver{
+    fn resolve(&self, index: u32, module: &str, field: &str) -> Option<wasmer::Export> {
+        match self.link(index, module, field){
+            Ok(v) => Some(v),
+            Err(e) => {
+                if let Some(stub) = self.weak_stub(index, module, field, &e){
+                    return Some(stub)
+                }
+                self.fail(e);
+                None
+            }
+        }
+    }
+}
+
+/// whether an export can satisfy an import.
+/// functions and globals must match exactly, memories and tables only need compatible limits.
+fn types_compatible(expected:&wasmer::ExternType, found:&wasmer::ExternType) -> bool{
+    match (expected, found){
+        (wasmer::ExternType::Function(a), wasmer::ExternType::Function(b)) => a == b,
+        (wasmer::ExternType::Global(a), wasmer::ExternType::Global(b)) => a == b,
+        (wasmer::ExternType::Memory(a), wasmer::ExternType::Memory(b)) => {
+            a.shared == b.shared && b.minimum >= a.minimum && match (a.maximum, b.maximum){
+                (None, _) => true,
+                (Some(am), Some(bm)) => bm <= am,
+                (Some(_), None) => false
+            }
+        },
+        (wasmer::ExternType::Table(a), wasmer::ExternType::Table(b)) => {
+            a.ty == b.ty && b.minimum >= a.minimum && match (a.maximum, b.maximum){
+                (None, _) => true,
+                (Some(am), Some(bm)) => bm <= am,
+                (Some(_), None) => false
+            }
+        },
+        _ => false
+    }
+}
 
 fn format_ty(t:&wasmer::ExternType) -> String{
     match t{
@@ -361,4 +1124,50 @@ fn format_ty(t:&wasmer::ExternType) -> String{
             format!("table {} minimum {}", t.ty, t.minimum)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// an emscripten style module: imported memory that cannot grow, `_malloc` and `_main(argc, argv)`
+    const EMSCRIPTEN_MAIN:&str = r#"
+        (module
+            (import "env" "memory" (memory 1 1))
+            (global $next (mut i32) (i32.const 1024))
+            (func (export "_malloc") (param $size i32) (result i32)
+                global.get $next
+                global.get $next
+                local.get $size
+                i32.add
+                global.set $next)
+            ;; argc * 100 + the first byte of argv[1]
+            (func (export "_main") (param $argc i32) (param $argv i32) (result i32)
+                local.get $argc
+                i32.const 100
+                i32.mul
+                local.get $argv
+                i32.load offset=4
+                i32.load8_u
+                i32.add))
+    "#;
+
+    #[test]
+    fn emscripten_main_gets_argv_and_returns_status(){
+        let features = features::profile("stable").unwrap();
+        let store = compiler::init(compiler::Backend::preferred(), &features);
+
+        let module = Module::new(&store, EMSCRIPTEN_MAIN).unwrap();
+        let memory = Memory::new(&store, wasmer::MemoryType::new(1, Some(1), false)).unwrap();
+        let imports = wasmer::imports!{
+            "env" => {
+                "memory" => memory.clone()
+            }
+        };
+        let instance = Instance::new(&module, &imports).unwrap();
+
+        let status = run_entry(&store, &instance, Some(&memory), "test", "_main", "prog", &["x"]);
+        assert_eq!(status, 2 * 100 + b'x' as i32);
+        assert_eq!(memory.size().0, 1);
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use sha2::{Digest, Sha256};
+use wasmer::Module;
+
+/// custom section pinning libraries to their contents, one `<name> <sha256>` per line
+pub const PIN_SECTION:&str = "libwasm.pins";
+
+static WARN_ONLY:AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static!{
+    /// expected hash of each pinned library.
+    /// the first module to pin a name wins, so the executable's pins override its libraries'.
+    static ref PINS:RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// report mismatching libraries but load them anyway
+pub fn warn_only(){
+    WARN_ONLY.store(true, Ordering::SeqCst);
+}
+
+pub fn is_warn_only() -> bool{
+    WARN_ONLY.load(Ordering::SeqCst)
+}
+
+/// lowercase hex SHA-256 of a module file
+pub fn hash(bytes:&[u8]) -> String{
+    Sha256::digest(bytes).iter().map(|b|{format!("{:02x}", b)}).collect()
+}
+
+/// add the pins a module declares
+pub fn record(module:&Module){
+    let mut pins = PINS.write().unwrap();
+
+    for section in module.custom_sections(PIN_SECTION){
+        for line in String::from_utf8_lossy(&section).lines(){
+            let line = line.trim_matches(|c:char|{c.is_whitespace() || c == '\0'});
+            let mut parts = line.split_whitespace();
+
+            if let (Some(name), Some(hash)) = (parts.next(), parts.next()){
+                pins.entry(name.to_string()).or_insert_with(||{hash.to_ascii_lowercase()});
+            }
+        }
+    }
+}
+
+pub fn is_pinned(name:&str) -> bool{
+    PINS.read().unwrap().contains_key(name)
+}
+
+/// the pinned hash of a library if it differs from `hash`
+pub fn mismatch(name:&str, hash:&str) -> Option<String>{
+    match PINS.read().unwrap().get(name){
+        Some(expected) if expected != hash => Some(expected.clone()),
+        _ => None
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::{Arc, RwLock};
+
+use wasmer::Extern;
+
+use crate::library::Library;
+
+/// colon or whitespace separated libraries loaded before any other, like LD_PRELOAD
+pub const PRELOAD_VAR:&str = "LIBWASM_PRELOAD";
+
+lazy_static::lazy_static!{
+    /// libraries given with --preload, in command line order
+    static ref REQUESTED:RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+    /// loaded preload libraries, consulted first when resolving function imports.
+    /// empty until every preload library is loaded so they do not interpose on each other.
+    static ref PRELOADED:RwLock<Vec<Arc<Library>>> = RwLock::new(Vec::new());
+}
+
+pub fn add(name:&str){
+    REQUESTED.write().unwrap().push(name.to_string());
+}
+
+/// --preload libraries followed by LIBWASM_PRELOAD
+pub fn requested() -> Vec<String>{
+    let mut names = REQUESTED.read().unwrap().clone();
+
+    if let Ok(var) = std::env::var(PRELOAD_VAR){
+        for name in var.split(|c:char|{c == ':' || c.is_whitespace()}){
+            if !name.is_empty() && !names.iter().any(|n|{n == name}){
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// preload libraries stay loaded for the life of the process
+pub fn activate(libraries:Vec<Arc<Library>>){
+    *PRELOADED.write().unwrap() = libraries;
+}
+
+/// the first preload library exporting a function with this name
+pub fn lookup(field:&str) -> Option<(String, Extern)>{
+    for library in PRELOADED.read().unwrap().iter(){
+        if let Some(ext @ Extern::Function(_)) = library.instance.exports.get_extern(field){
+            return Some((library.name.clone(), ext.clone()))
+        }
+    }
+    None
+}
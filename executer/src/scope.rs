@@ -0,0 +1,122 @@
+use std::sync::{Arc, RwLock, Weak};
+
+use wasmer::{Extern, ExternType, Module};
+
+use crate::dylink;
+use crate::library::Library;
+
+/// imports from this module are looked up in the global scope instead of a library file
+pub const SCOPE_MODULE:&str = "env";
+
+/// custom section listing needed libraries of a module without dylink.0, one name per line
+pub const NEEDED_SECTION:&str = "libwasm.needed";
+
+lazy_static::lazy_static!{
+    /// loaded libraries in symbol lookup order.
+    /// a slot is reserved when a library is declared as needed and filled once it is loaded.
+    /// the scope does not keep libraries loaded, their importers do.
+    static ref SCOPE:RwLock<Vec<(String, Option<Weak<Library>>)>> = RwLock::new(Vec::new());
+
+    /// the executable and the functions it exports.
+    /// it comes first in lookup order but is instantiated after the libraries it needs.
+    static ref EXECUTABLE:RwLock<Option<(String, Vec<String>)>> = RwLock::new(None);
+}
+
+/// libraries a module declares as needed, in declaration order
+pub fn needed(module:&Module) -> Vec<String>{
+    let mut needed = Vec::new();
+
+    if let Some(info) = dylink::parse(module){
+        needed.extend(info.needed);
+    }
+
+    for section in module.custom_sections(NEEDED_SECTION){
+        for line in String::from_utf8_lossy(&section).lines(){
+            let name = line.trim_matches(|c:char|{c.is_whitespace() || c == '\0'});
+            if !name.is_empty() && !needed.iter().any(|n|{n == name}){
+                needed.push(name.to_string());
+            }
+        }
+    }
+    needed
+}
+
+/// reserve lookup order for libraries before they are loaded.
+/// a module's needed libraries come after everything declared before them,
+/// giving the breadth first order of ELF's global scope.
+pub fn declare(names:&[String]){
+    let mut scope = SCOPE.write().unwrap();
+    for name in names{
+        if !scope.iter().any(|(n, _)|{n == name}){
+            scope.push((name.clone(), None));
+        }
+    }
+}
+
+/// reserve the first slot for the executable, before any library is loaded
+pub fn declare_executable(name:&str, module:&Module){
+    let functions = module.exports()
+        .filter(|e|{matches!(e.ty(), ExternType::Function(_))})
+        .map(|e|{e.name().to_string()})
+        .collect();
+
+    *EXECUTABLE.write().unwrap() = Some((name.to_string(), functions));
+    declare(&[name.to_string()]);
+}
+
+/// whether a function is defined by the executable, which is not instantiated yet
+pub fn defined_by_executable(field:&str) -> bool{
+    let executable = EXECUTABLE.read().unwrap();
+    let (name, functions) = match executable.as_ref(){
+        Some(e) => e,
+        None => return false
+    };
+
+    let instantiated = SCOPE.read().unwrap().iter().any(|(n, slot)|{n == name && slot.is_some()});
+    !instantiated && functions.iter().any(|f|{f == field})
+}
+
+pub fn add(library:&Arc<Library>){
+    let mut scope = SCOPE.write().unwrap();
+    if let Some((_, slot)) = scope.iter_mut().find(|(n, _)|{n == &library.name}){
+        let live = slot.as_ref().map(|w|{w.strong_count() > 0}).unwrap_or(false);
+        if !live{
+            *slot = Some(Arc::downgrade(library));
+        }
+    } else{
+        scope.push((library.name.clone(), Some(Arc::downgrade(library))));
+    }
+}
+
+/// forget an unloaded library, a reloaded copy under the same name is kept
+pub fn remove(name:&str){
+    SCOPE.write().unwrap().retain(|(n, slot)|{
+        n != name || slot.as_ref().map(|w|{w.strong_count() > 0}).unwrap_or(true)
+    });
+}
+
+/// find the first loaded library exporting a symbol
+pub fn lookup(field:&str) -> Option<(Arc<Library>, Extern)>{
+    // upgrade under the lock but search outside it, dropping a handle may unload a library
+    let libraries:Vec<Arc<Library>> = SCOPE.read().unwrap().iter()
+        .filter_map(|(_, slot)|{slot.as_ref().and_then(|w|{w.upgrade()})})
+        .collect();
+
+    for library in libraries{
+        if let Some(ext) = library.instance.exports.get_extern(field).cloned(){
+            return Some((library, ext))
+        }
+    }
+    None
+}
+
+/// libraries declared as needed but not loaded yet, in lookup order.
+/// the executable is not a library, it is never loaded from here.
+pub fn pending() -> Vec<String>{
+    let executable = EXECUTABLE.read().unwrap().as_ref().map(|(n, _)|{n.clone()});
+
+    SCOPE.read().unwrap().iter()
+        .filter(|(n, i)|{i.is_none() && Some(n) != executable.as_ref()})
+        .map(|(n, _)|{n.clone()})
+        .collect()
+}
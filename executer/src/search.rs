@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::ldconfig;
+
+/// colon separated list of directories searched after -ld
+pub const LIBRARY_PATH_VAR:&str = "LIBWASM_LIBRARY_PATH";
+
+/// default system directories, searched last
+pub const SYSTEM_DIRECTORIES:&[&str] = &[
+    "/usr/local/lib/wasm",
+    "/usr/lib/wasm",
+];
+
+lazy_static::lazy_static!{
+    /// directories added with -ld, in command line order
+    static ref LINK_DIRECTORIES:RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+    /// directory containing the wasm executable
+    static ref EXECUTABLE_DIRECTORY:RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+pub fn add_link_directory(dir:&str){
+    LINK_DIRECTORIES.write().unwrap().push(PathBuf::from(dir));
+}
+
+pub fn set_executable(path:&str){
+    let dir = Path::new(path)
+        .canonicalize()
+        .ok()
+        .and_then(|p|{p.parent().map(|d|{d.to_path_buf()})});
+
+    *EXECUTABLE_DIRECTORY.write().unwrap() = dir;
+}
+
+/// directories searched before the ldconfig cache
+fn user_paths() -> Vec<PathBuf>{
+    let mut paths = LINK_DIRECTORIES.read().unwrap().clone();
+
+    if let Some(var) = std::env::var_os(LIBRARY_PATH_VAR){
+        for dir in std::env::split_paths(&var){
+            // empty entries are ignored rather than meaning the current directory
+            if !dir.as_os_str().is_empty(){
+                paths.push(dir);
+            }
+        }
+    }
+
+    if let Some(dir) = EXECUTABLE_DIRECTORY.read().unwrap().as_ref(){
+        paths.push(dir.clone());
+    }
+
+    if let Ok(dir) = std::env::current_dir(){
+        paths.push(dir);
+    }
+
+    paths
+}
+
+pub fn system_directories() -> Vec<PathBuf>{
+    let mut dirs = Vec::new();
+
+    if let Some(data) = data_home(){
+        dirs.push(data.join("libwasm").join("lib"));
+    }
+
+    for dir in SYSTEM_DIRECTORIES{
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+/// $XDG_DATA_HOME, defaults to ~/.local/share
+fn data_home() -> Option<PathBuf>{
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME"){
+        if !dir.is_empty(){
+            return Some(PathBuf::from(dir))
+        }
+    }
+    std::env::var_os("HOME").map(|home|{PathBuf::from(home).join(".local").join("share")})
+}
+
+/// find a library by its file name, returns the canonical path of the first match.
+/// the search order is -ld directories, LIBWASM_LIBRARY_PATH, the executable's directory,
+/// the current directory, the ldconfig cache, then the system directories.
+pub fn find_library(name:&str) -> Option<PathBuf>{
+    find_in(&user_paths(), name)
+        .or_else(||{ldconfig::lookup(name)})
+        .or_else(||{find_in(&system_directories(), name)})
+}
+
+fn find_in(dirs:&[PathBuf], name:&str) -> Option<PathBuf>{
+    for dir in dirs{
+        let path = dir.join(name);
+
+        if path.is_file(){
+            return path.canonicalize().ok()
+        }
+    }
+    None
+}
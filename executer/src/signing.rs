@@ -0,0 +1,401 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::artifact;
+
+/// custom section holding the signature of the rest of the module
+pub const SIGNATURE_SECTION:&str = "libwasm.sig";
+
+/// extension of detached signature files, `libfoo.wasm.sig`
+pub const DETACHED_EXTENSION:&str = "sig";
+
+/// trusted publisher keys, one hex encoded `*.pub` file per key
+pub const SYSTEM_KEYRING:&str = "/etc/libwasm/keys";
+
+static REQUIRE_SIGNED:AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static!{
+    /// keyring directories added with --keyring
+    static ref KEYRINGS:RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+}
+
+pub fn require_signed(){
+    REQUIRE_SIGNED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_required() -> bool{
+    REQUIRE_SIGNED.load(Ordering::SeqCst)
+}
+
+pub fn add_keyring(dir:&str){
+    KEYRINGS.write().unwrap().push(PathBuf::from(dir));
+}
+
+/// --keyring directories, then the system keyring
+fn keyrings() -> Vec<PathBuf>{
+    let mut dirs = KEYRINGS.read().unwrap().clone();
+    dirs.push(PathBuf::from(SYSTEM_KEYRING));
+    dirs
+}
+
+fn trusted_keys() -> Vec<VerifyingKey>{
+    let mut keys = Vec::new();
+
+    for dir in keyrings(){
+        let entries = match std::fs::read_dir(&dir){
+            Ok(e) => e,
+            Err(_) => continue
+        };
+
+        for entry in entries.flatten(){
+            let path = entry.path();
+            if path.extension().map(|e|{e == "pub"}).unwrap_or(false){
+                if let Some(key) = read_hex_file::<32>(&path).and_then(|b|{VerifyingKey::from_bytes(&b).ok()}){
+                    keys.push(key);
+                }
+            }
+        }
+    }
+    keys
+}
+
+/// check a module against the keyring.
+/// the signature is taken from the custom section, or else from the detached file.
+pub fn verify(path:&Path, bytes:&[u8]) -> Result<(), String>{
+    verify_with(path, bytes, &trusted_keys())
+}
+
+fn verify_with(path:&Path, bytes:&[u8], keys:&[VerifyingKey]) -> Result<(), String>{
+    let (message, signature) = match embedded_signature(bytes){
+        Some(sig) => (strip_signature(bytes), sig),
+        None => {
+            let sig_path = detached_path(path);
+            let sig = std::fs::read(&sig_path).map_err(|_|{"module is not signed".to_string()})?;
+            (bytes.to_vec(), sig)
+        }
+    };
+
+    let signature:[u8; 64] = signature.as_slice().try_into().map_err(|_|{"malformed signature".to_string()})?;
+    let signature = Signature::from_bytes(&signature);
+
+    if keys.is_empty(){
+        return Err("no trusted keys in the keyring".to_string())
+    }
+
+    if keys.iter().any(|k|{k.verify(&message, &signature).is_ok()}){
+        return Ok(())
+    }
+    Err("signature does not match any trusted key".to_string())
+}
+
+fn detached_path(path:&Path) -> PathBuf{
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(DETACHED_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// top level sections of a module as (id, start, end), including the section header
+fn sections(bytes:&[u8]) -> Vec<(u8, usize, usize)>{
+    let mut sections = Vec::new();
+    // skip magic and version
+    let mut pos = 8;
+
+    while pos < bytes.len(){
+        let start = pos;
+        let id = bytes[pos];
+        pos += 1;
+
+        let size = match read_leb(bytes, &mut pos){
+            Some(s) => s as usize,
+            None => break
+        };
+        if pos + size > bytes.len(){
+            break;
+        }
+        pos += size;
+        sections.push((id, start, pos));
+    }
+    sections
+}
+
+/// name and payload of a custom section
+fn custom_section(bytes:&[u8], start:usize, end:usize) -> Option<(&str, &[u8])>{
+    let mut pos = start + 1;
+    read_leb(bytes, &mut pos)?;
+    let len = read_leb(bytes, &mut pos)? as usize;
+    let name = std::str::from_utf8(bytes.get(pos..pos + len)?).ok()?;
+    Some((name, bytes.get(pos + len..end)?))
+}
+
+fn read_leb(bytes:&[u8], pos:&mut usize) -> Option<u32>{
+    let mut result = 0u32;
+    let mut shift = 0;
+    loop{
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0{
+            return Some(result)
+        }
+        shift += 7;
+        if shift >= 35{
+            return None
+        }
+    }
+}
+
+fn write_leb(out:&mut Vec<u8>, mut value:u32){
+    loop{
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0{
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn embedded_signature(bytes:&[u8]) -> Option<Vec<u8>>{
+    // precompiled artifacts only carry detached signatures
+    if !bytes.starts_with(b"\0asm"){
+        return None
+    }
+
+    for (id, start, end) in sections(bytes){
+        if id == 0{
+            if let Some((SIGNATURE_SECTION, payload)) = custom_section(bytes, start, end){
+                return Some(payload.to_vec())
+            }
+        }
+    }
+    None
+}
+
+/// the module without its signature section, this is what gets signed
+fn strip_signature(bytes:&[u8]) -> Vec<u8>{
+    let mut out = bytes[..8.min(bytes.len())].to_vec();
+
+    for (id, start, end) in sections(bytes){
+        if id == 0{
+            if let Some((SIGNATURE_SECTION, _)) = custom_section(bytes, start, end){
+                continue;
+            }
+        }
+        out.extend_from_slice(&bytes[start..end]);
+    }
+    out
+}
+
+fn signature_section(signature:&[u8]) -> Vec<u8>{
+    let mut payload = Vec::new();
+    write_leb(&mut payload, SIGNATURE_SECTION.len() as u32);
+    payload.extend_from_slice(SIGNATURE_SECTION.as_bytes());
+    payload.extend_from_slice(signature);
+
+    let mut section = vec![0u8];
+    write_leb(&mut section, payload.len() as u32);
+    section.extend_from_slice(&payload);
+    section
+}
+
+fn to_hex(bytes:&[u8]) -> String{
+    bytes.iter().map(|b|{format!("{:02x}", b)}).collect()
+}
+
+fn read_hex_file<const N:usize>(path:&Path) -> Option<[u8; N]>{
+    let text = std::fs::read_to_string(path).ok()?;
+    let text = text.trim();
+    if text.len() != N * 2{
+        return None
+    }
+
+    let mut out = [0u8; N];
+    for i in 0..N{
+        out[i] = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+const SIGN_USAGE:&str = "usage: libwasm sign <lib.wasm> --key <secret.key> [--detached]
+       libwasm sign --keygen <name>";
+
+/// `libwasm sign`
+pub fn command(args:&[String]){
+    let mut input = None;
+    let mut key = None;
+    let mut keygen = None;
+    let mut detached = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next(){
+        if arg == "--key"{
+            key = iter.next().cloned();
+        } else if arg == "--keygen"{
+            keygen = iter.next().cloned();
+        } else if arg == "--detached"{
+            detached = true;
+        } else{
+            input = Some(arg.clone());
+        }
+    }
+
+    let result = if let Some(name) = keygen{
+        generate_key(&name)
+    } else if let (Some(input), Some(key)) = (input, key){
+        sign(Path::new(&input), Path::new(&key), detached)
+    } else{
+        eprintln!("{}", SIGN_USAGE);
+        std::process::exit(2);
+    };
+
+    if let Err(e) = result{
+        eprintln!("libwasm: sign: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// writes `<name>.key`, keep it private, and `<name>.pub` for the keyring
+fn generate_key(name:&str) -> Result<(), String>{
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).map_err(|e|{e.to_string()})?;
+
+    let key = SigningKey::from_bytes(&secret);
+
+    // readable by the owner only, and never over an existing key
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(format!("{}.key", name)).map_err(|e|{format!("{}.key: {}", name, e)})?;
+    file.write_all(to_hex(&secret).as_bytes()).map_err(|e|{e.to_string()})?;
+
+    std::fs::write(format!("{}.pub", name), to_hex(key.verifying_key().as_bytes())).map_err(|e|{e.to_string()})?;
+
+    println!("wrote {0}.key and {0}.pub", name);
+    Ok(())
+}
+
+fn sign(input:&Path, key:&Path, detached:bool) -> Result<(), String>{
+    let secret = read_hex_file::<32>(key).ok_or_else(||{format!("invalid key file {}", key.display())})?;
+    let key = SigningKey::from_bytes(&secret);
+
+    let bytes = std::fs::read(input).map_err(|e|{format!("{}: {}", input.display(), e)})?;
+
+    // an artifact cannot hold a custom section, its signature covers the whole file
+    if artifact::is_artifact(&bytes){
+        if !detached{
+            return Err(format!("{} is precompiled, sign it with --detached", input.display()))
+        }
+        let sig_path = detached_path(input);
+        std::fs::write(&sig_path, key.sign(&bytes).to_bytes()).map_err(|e|{e.to_string()})?;
+        println!("wrote {}", sig_path.display());
+        return Ok(())
+    }
+
+    if !bytes.starts_with(b"\0asm"){
+        return Err(format!("{} is not a wasm module", input.display()))
+    }
+
+    // re-signing replaces the old signature
+    let mut module = strip_signature(&bytes);
+    let signature = key.sign(&module).to_bytes();
+
+    if detached{
+        let sig_path = detached_path(input);
+        std::fs::write(&sig_path, signature).map_err(|e|{e.to_string()})?;
+        println!("wrote {}", sig_path.display());
+    } else{
+        module.extend_from_slice(&signature_section(&signature));
+        std::fs::write(input, module).map_err(|e|{e.to_string()})?;
+        println!("signed {}", input.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// header and a custom section "data"
+    fn module() -> Vec<u8>{
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        bytes.extend_from_slice(&[0, 8, 4, b'd', b'a', b't', b'a', 1, 2, 3]);
+        bytes
+    }
+
+    fn temp_path(name:&str) -> PathBuf{
+        std::env::temp_dir().join(format!("libwasm-signing-{}-{}", std::process::id(), name))
+    }
+
+    /// a module and a key file for `sign`, returns the public key
+    fn setup(name:&str) -> (PathBuf, PathBuf, VerifyingKey){
+        let input = temp_path(&format!("{}.wasm", name));
+        let key_path = temp_path(&format!("{}.key", name));
+        let secret = [7u8; 32];
+
+        std::fs::write(&input, module()).unwrap();
+        std::fs::write(&key_path, to_hex(&secret)).unwrap();
+        (input, key_path, SigningKey::from_bytes(&secret).verifying_key())
+    }
+
+    fn cleanup(paths:&[&Path]){
+        for path in paths{
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn strip_signature_round_trip(){
+        let mut signed = module();
+        signed.extend_from_slice(&signature_section(&[9u8; 64]));
+
+        assert_eq!(embedded_signature(&signed), Some(vec![9u8; 64]));
+        assert_eq!(strip_signature(&signed), module());
+        assert_eq!(embedded_signature(&module()), None);
+    }
+
+    #[test]
+    fn sign_then_verify_embedded(){
+        let (input, key, public) = setup("embedded");
+        sign(&input, &key, false).unwrap();
+
+        let bytes = std::fs::read(&input).unwrap();
+        assert!(verify_with(&input, &bytes, &[public]).is_ok());
+
+        // a changed payload byte invalidates the signature
+        let mut tampered = bytes.clone();
+        tampered[16] ^= 1;
+        assert!(verify_with(&input, &tampered, &[public]).is_err());
+
+        let other = SigningKey::from_bytes(&[8u8; 32]).verifying_key();
+        assert!(verify_with(&input, &bytes, &[other]).is_err());
+        assert!(verify_with(&input, &bytes, &[]).is_err());
+
+        cleanup(&[&input, &key]);
+    }
+
+    #[test]
+    fn sign_then_verify_detached(){
+        let (input, key, public) = setup("detached");
+        sign(&input, &key, true).unwrap();
+
+        // the module itself is left as it was
+        let bytes = std::fs::read(&input).unwrap();
+        assert_eq!(bytes, module());
+        assert!(verify_with(&input, &bytes, &[public]).is_ok());
+
+        std::fs::remove_file(detached_path(&input)).unwrap();
+        assert_eq!(verify_with(&input, &bytes, &[public]), Err("module is not signed".to_string()));
+
+        cleanup(&[&input, &key]);
+    }
+}
@@ -0,0 +1,42 @@
+use wasmer::{Function, FunctionType, Module, RuntimeError, Store};
+
+/// custom section listing optional imports, one `module::field` per line.
+/// `module::*` marks every import from a module as optional.
+pub const WEAK_SECTION:&str = "libwasm.weak";
+
+/// optional imports declared by a module
+#[derive(Debug, Default, Clone)]
+pub struct WeakImports{
+    entries:Vec<(String, String)>
+}
+
+impl WeakImports{
+    pub fn parse(module:&Module) -> Self{
+        let mut entries = Vec::new();
+
+        for section in module.custom_sections(WEAK_SECTION){
+            for line in String::from_utf8_lossy(&section).lines(){
+                let line = line.trim_matches(|c:char|{c.is_whitespace() || c == '\0'});
+                if let Some((module, field)) = line.split_once("::"){
+                    entries.push((module.to_string(), field.to_string()));
+                }
+            }
+        }
+        Self {
+            entries
+        }
+    }
+
+    pub fn contains(&self, module:&str, field:&str) -> bool{
+        self.entries.iter().any(|(m, f)|{m == module && (f == field || f == "*")})
+    }
+}
+
+/// a function of the import's signature that traps when called
+pub fn stub(store:&Store, ty:&FunctionType, module:&str, field:&str, reason:String) -> Function{
+    let message = format!("optional symbol {}::{} was called but is not available: {}", module, field, reason);
+
+    Function::new(store, ty, move |_args|{
+        Err(RuntimeError::new(message.clone()))
+    })
+}